@@ -1,18 +1,308 @@
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use std::collections::BTreeMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
+use crate::error::{Error, Result};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Feed {
     pub link: String,
     pub schedule: String,
+    // overrides [templates] for this feed, e.g. templates = { subject = "..." }
+    #[serde(default, skip_serializing_if = "Templates::is_empty")]
+    pub templates: Templates,
+}
+
+// Notification subject and body, with {{placeholders}} for the feed and its
+// newest item. The body may use the <b>, <i>, <u> and <a> markup desktop
+// notifications understand; notifiers that only show text drop it.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Templates {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+impl Templates {
+    pub fn is_empty(&self) -> bool {
+        self.subject.is_none() && self.body.is_none()
+    }
+
+    // What's set here wins, the rest comes from fallback.
+    pub fn or(&self, fallback: &Templates) -> Templates {
+        Templates {
+            subject: self.subject.clone().or_else(|| fallback.subject.clone()),
+            body: self.body.clone().or_else(|| fallback.body.clone()),
+        }
+    }
+}
+
+// How feeds are fetched: how many at once, and the HTTP client settings.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FetchConfig {
+    pub max_concurrent: usize,
+    pub max_concurrent_per_host: usize,
+    pub connect_timeout_secs: u64,
+    pub read_timeout_secs: u64,
+    pub user_agent: String,
+    pub max_body_bytes: u64,
+    pub max_redirects: usize,
+    // retries within a single check
+    pub max_retries: u32,
+    pub retry_base_delay_ms: u64,
+    pub max_retry_wait_secs: u64,
+    // how long a failing feed is left alone across checks
+    pub failure_backoff_base_secs: u64,
+    pub failure_backoff_max_secs: u64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            max_concurrent: 8,
+            max_concurrent_per_host: 2,
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            user_agent: format!(
+                "rss-notify/{} (+https://github.com/martinezjandrew/rss-notify)",
+                env!("CARGO_PKG_VERSION")
+            ),
+            max_body_bytes: 10 * 1024 * 1024,
+            max_redirects: 5,
+            max_retries: 2,
+            retry_base_delay_ms: 500,
+            max_retry_wait_secs: 30,
+            failure_backoff_base_secs: 60,
+            failure_backoff_max_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+// Where notifications about new items are delivered. Each entry is a
+// [[notifiers]] table picked by its `type`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    Desktop(DesktopConfig),
+    Webhook(WebhookConfig),
+    Email(EmailConfig),
+    Ntfy(NtfyConfig),
+    Gotify(GotifyConfig),
+    Jsonl(JsonlConfig),
+    Exec(ExecConfig),
+}
+
+impl NotifierConfig {
+    // The `type` it's configured with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NotifierConfig::Desktop(_) => "desktop",
+            NotifierConfig::Webhook(_) => "webhook",
+            NotifierConfig::Email(_) => "email",
+            NotifierConfig::Ntfy(_) => "ntfy",
+            NotifierConfig::Gotify(_) => "gotify",
+            NotifierConfig::Jsonl(_) => "jsonl",
+            NotifierConfig::Exec(_) => "exec",
+        }
+    }
+}
+
+// One popup per feed by default. With `per = "item"` every new item gets its own,
+// with Open, Mark read, Snooze 1h and Mute feed actions, up to `max_items` in a
+// check; whatever is left over is summed up per feed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct DesktopConfig {
+    pub per: DesktopPer,
+    pub max_items: usize,
+    // feed icons and item images, cached in the data directory
+    pub images: bool,
+}
+
+impl Default for DesktopConfig {
+    fn default() -> Self {
+        DesktopConfig {
+            per: DesktopPer::Feed,
+            max_items: 5,
+            images: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DesktopPer {
+    #[default]
+    Feed,
+    Item,
+}
+
+// POSTs each feed's new items to `url`. Without a `body` template the default
+// JSON payload is sent as is.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default = "default_notifier_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_notifier_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_notifier_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+}
+
+// Sends new items over SMTP, either one email per feed as it's checked or a
+// single digest of everything new in a check run. The username and password can
+// come from the environment instead, through username_env and password_env.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EmailConfig {
+    pub host: String,
+    // defaults to 587 for starttls, 465 for tls and 25 for plain
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub username_env: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub password_env: Option<String>,
+    #[serde(default)]
+    pub mode: EmailMode,
+    #[serde(default = "default_notifier_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    Starttls,
+    Tls,
+    // unencrypted, only for local relays
+    Plain,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailMode {
+    #[default]
+    Immediate,
+    Digest,
+}
+
+// Publishes to an ntfy topic. priority is 1 (min) to 5 (max), tags are ntfy
+// tags or emoji shortcodes.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NtfyConfig {
+    #[serde(default = "default_ntfy_server")]
+    pub server: String,
+    pub topic: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub token_env: Option<String>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "default_notifier_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_ntfy_server() -> String {
+    String::from("https://ntfy.sh")
+}
+
+// Sends a message as a Gotify application. priority is 0 to 10.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GotifyConfig {
+    pub server: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub token_env: Option<String>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+    #[serde(default = "default_notifier_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+// Writes one JSON object per new item, appending to `path` or to stdout.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct JsonlConfig {
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+// Runs `command` (program then arguments, no shell) for every new item, or
+// once per feed, with the details in RSS_NOTIFY_* variables and as JSON on stdin.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExecConfig {
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub per: ExecPer,
+    #[serde(default = "default_notifier_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecPer {
+    #[default]
+    Item,
+    Feed,
+}
+
+fn default_notifier_timeout_secs() -> u64 {
+    30
+}
+
+fn default_notifier_max_retries() -> u32 {
+    2
+}
+
+fn default_notifier_retry_base_delay_ms() -> u64 {
+    500
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub feeds: Vec<Feed>,
+    #[serde(default)]
+    pub fetch: FetchConfig,
+    #[serde(default = "default_notifiers")]
+    pub notifiers: Vec<NotifierConfig>,
+    #[serde(default, skip_serializing_if = "Templates::is_empty")]
+    pub templates: Templates,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            feeds: Vec::new(),
+            fetch: FetchConfig::default(),
+            notifiers: default_notifiers(),
+            templates: Templates::default(),
+        }
+    }
+}
+
+fn default_notifiers() -> Vec<NotifierConfig> {
+    vec![NotifierConfig::Desktop(DesktopConfig::default())]
 }
 
 impl Config {
@@ -20,17 +310,33 @@ impl Config {
         let new_feed = Feed {
             link: url.to_string(),
             schedule: schedule.to_string(),
+            templates: Templates::default(),
         };
         self.feeds.push(new_feed);
     }
-    pub fn remove_feed(&mut self, index: usize) -> Result<(), &'static str> {
+    pub fn remove_feed(&mut self, index: usize) -> Result<()> {
         if index >= self.feeds.len() {
-            Err("Feed index is out of bounds.")
+            Err(Error::Config {
+                reason: format!("Feed index {} is out of bounds.", index),
+                source: None,
+            })
         } else {
             self.feeds.remove(index);
             Ok(())
         }
     }
+    // The feed's own templates, falling back to [templates]. links are every
+    // address the feed has had, since it may have moved since it was configured.
+    pub fn templates_for(&self, links: &[&str]) -> Templates {
+        let configured = links
+            .iter()
+            .find_map(|link| self.feeds.iter().find(|feed| feed.link == *link));
+        match configured {
+            Some(feed) => feed.templates.or(&self.templates),
+            None => self.templates.clone(),
+        }
+    }
+
     pub fn list_feeds(&self) -> String {
         let feed_iter = self.feeds.iter();
 
@@ -49,31 +355,32 @@ impl Config {
         self.feeds.clear();
     }
 
-    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
-        let path = get_config_path(path);
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = get_config_path(path)?;
 
         if path.exists() {
-            let contents = fs::read_to_string(&path)?;
-            let config = toml::from_str(&contents).expect("Failed to fit into the Config class");
-            return Ok(config);
+            let contents = fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+            return toml::from_str(&contents)
+                .map_err(|e| Error::config(format!("Invalid config file {}", path.display()), e));
         }
 
         let default = Config {
             feeds: vec![Feed {
                 link: "https://archlinux.org/news/".into(),
                 schedule: "* * * * *".into(),
+                templates: Templates::default(),
             }],
+            ..Default::default()
         };
         create_config(&path, &default)?;
         Ok(default)
     }
 
-    pub fn save(&self, path: Option<&str>) -> Result<(), Box<dyn Error>> {
-        let path = get_config_path(path);
+    pub fn save(&self, path: Option<&str>) -> Result<()> {
+        let path = get_config_path(path)?;
 
         if path.exists() {
-            let toml_file = toml::to_string_pretty(self)?;
-            fs::write(&path, toml_file)?;
+            write_toml(&path, self)?;
         } else {
             create_config(&path, self)?;
         }
@@ -81,35 +388,40 @@ impl Config {
     }
 }
 
-fn get_config_path(path: Option<&str>) -> PathBuf {
-    if let Some(p) = path {
-        let dir = PathBuf::from(p);
-        fs::create_dir_all(&dir).expect("Failed to create config directory.");
-        return dir.join("config.toml");
-    }
-
-    let dirs = ProjectDirs::from("com", "martinezjandrew", "rss-notify")
-        .expect("Unable to get config directory.");
-
-    let config_dir = dirs.config_dir();
-    fs::create_dir_all(config_dir).expect("Failed to create config directory.");
-
-    config_dir.join("config.toml")
+fn get_config_path(path: Option<&str>) -> Result<PathBuf> {
+    let config_dir = match path {
+        Some(p) => PathBuf::from(p),
+        None => ProjectDirs::from("com", "martinezjandrew", "rss-notify")
+            .ok_or_else(|| Error::Config {
+                reason: "Unable to find a config directory".to_string(),
+                source: None,
+            })?
+            .config_dir()
+            .to_path_buf(),
+    };
+
+    fs::create_dir_all(&config_dir).map_err(|e| Error::io(&config_dir, e))?;
+    Ok(config_dir.join("config.toml"))
 }
 
-fn create_config(path: &Path, config: &Config) -> Result<PathBuf, Box<dyn Error>> {
+fn create_config(path: &Path, config: &Config) -> Result<PathBuf> {
     if path.exists() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
-            "Config path already exists.",
-        )));
+        return Err(Error::io(
+            path,
+            io::Error::new(io::ErrorKind::AlreadyExists, "Config path already exists."),
+        ));
     }
 
-    let toml_str = toml::to_string_pretty(&config)?;
-    fs::write(path, toml_str)?;
+    write_toml(path, config)?;
     Ok(path.to_path_buf())
 }
 
+fn write_toml(path: &Path, config: &Config) -> Result<()> {
+    let toml_str = toml::to_string_pretty(config)
+        .map_err(|e| Error::config(format!("Failed to serialize {}", path.display()), e))?;
+    fs::write(path, toml_str).map_err(|e| Error::io(path, e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,6 +445,7 @@ mod tests {
 
         assert!(config.remove_feed(0).is_ok());
         assert_eq!(config.feeds.len(), 0);
+        assert!(config.remove_feed(0).is_err());
     }
     #[test]
     fn list_feeds() {
@@ -150,7 +463,7 @@ mod tests {
     #[test]
     fn test_temp_config_path() {
         let test_path = "./test-temp-config-path";
-        let path = get_config_path(Some(test_path));
+        let path = get_config_path(Some(test_path)).unwrap();
         assert!(path.ends_with("config.toml"));
 
         std::fs::remove_dir_all(test_path).ok();
@@ -163,6 +476,18 @@ mod tests {
         std::fs::remove_dir_all(test_path).ok();
     }
     #[test]
+    fn test_invalid_config_file() {
+        let test_path = "./test-invalid-config-file";
+        let path = get_config_path(Some(test_path)).unwrap();
+        std::fs::write(&path, "feeds = \"not a list\"").unwrap();
+
+        let error = Config::load(Some(test_path)).err().unwrap();
+        assert!(matches!(error, Error::Config { .. }));
+        assert!(error.report().contains("config.toml"));
+
+        std::fs::remove_dir_all(test_path).ok();
+    }
+    #[test]
     fn add_to_and_remove_from_temp_config_file() {
         let test_path = "./test-add-to-and-remove-from-temp-config-file";
         let mut config = Config::load(Some(test_path)).expect("Failed to load or create config");
@@ -213,6 +538,268 @@ mod tests {
         std::fs::remove_dir_all(test_path).ok();
     }
 
+    #[test]
+    fn test_fetch_config_defaults_when_missing() {
+        let config: Config = toml::from_str(
+            r#"
+            [[feeds]]
+            link = "https://feeds.npr.org/1001/rss.xml"
+            schedule = "* * * * *"
+            "#,
+        )
+        .expect("Old configs without [fetch] should still load");
+
+        assert_eq!(config.fetch.max_concurrent, 8);
+        assert_eq!(config.fetch.max_concurrent_per_host, 2);
+        assert_eq!(
+            config.notifiers,
+            vec![NotifierConfig::Desktop(DesktopConfig::default())]
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [fetch]
+            max_concurrent = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.fetch.max_concurrent, 3);
+        assert_eq!(config.fetch.max_concurrent_per_host, 2);
+        assert!(config.fetch.user_agent.starts_with("rss-notify/"));
+    }
+
+    #[test]
+    fn test_notifiers_round_trip() {
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [[notifiers]]
+            type = "desktop"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.notifiers,
+            vec![NotifierConfig::Desktop(DesktopConfig::default())]
+        );
+
+        let saved = toml::to_string_pretty(&config).unwrap();
+        let reloaded: Config = toml::from_str(&saved).unwrap();
+        assert_eq!(reloaded.notifiers, config.notifiers);
+
+        let config: Config = toml::from_str("feeds = []\nnotifiers = []").unwrap();
+        assert!(
+            config.notifiers.is_empty(),
+            "No notifiers is a valid choice"
+        );
+    }
+
+    #[test]
+    fn test_webhook_notifier_config() {
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [[notifiers]]
+            type = "webhook"
+            url = "https://chat.example.com/hooks/abc"
+            headers = { Authorization = "Bearer secret" }
+            "#,
+        )
+        .unwrap();
+
+        let NotifierConfig::Webhook(webhook) = &config.notifiers[0] else {
+            panic!("Expected a webhook notifier");
+        };
+        assert_eq!(webhook.url, "https://chat.example.com/hooks/abc");
+        assert_eq!(webhook.headers["Authorization"], "Bearer secret");
+        assert_eq!(webhook.body, None);
+        assert_eq!(webhook.max_retries, 2);
+    }
+
+    #[test]
+    fn test_email_notifier_config() {
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [[notifiers]]
+            type = "email"
+            host = "smtp.example.com"
+            from = "rss-notify <feeds@example.com>"
+            to = ["me@example.com"]
+            username = "feeds"
+            password_env = "SMTP_PASSWORD"
+            mode = "digest"
+            "#,
+        )
+        .unwrap();
+
+        let NotifierConfig::Email(email) = &config.notifiers[0] else {
+            panic!("Expected an email notifier");
+        };
+        assert_eq!(email.security, SmtpSecurity::Starttls);
+        assert_eq!(email.mode, EmailMode::Digest);
+        assert_eq!(email.port, None);
+        assert_eq!(email.password_env.as_deref(), Some("SMTP_PASSWORD"));
+    }
+
+    #[test]
+    fn test_push_notifier_config() {
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [[notifiers]]
+            type = "ntfy"
+            topic = "my-feeds"
+            tags = ["newspaper"]
+
+            [[notifiers]]
+            type = "gotify"
+            server = "https://gotify.example.com"
+            token_env = "GOTIFY_TOKEN"
+            priority = 8
+            "#,
+        )
+        .unwrap();
+
+        let NotifierConfig::Ntfy(ntfy) = &config.notifiers[0] else {
+            panic!("Expected an ntfy notifier");
+        };
+        assert_eq!(ntfy.server, "https://ntfy.sh");
+        assert_eq!(ntfy.tags, vec!["newspaper"]);
+        assert_eq!(ntfy.priority, None);
+
+        let NotifierConfig::Gotify(gotify) = &config.notifiers[1] else {
+            panic!("Expected a gotify notifier");
+        };
+        assert_eq!(gotify.token_env.as_deref(), Some("GOTIFY_TOKEN"));
+        assert_eq!(gotify.priority, Some(8));
+    }
+
+    #[test]
+    fn test_jsonl_notifier_config() {
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [[notifiers]]
+            type = "jsonl"
+
+            [[notifiers]]
+            type = "jsonl"
+            path = "/var/log/rss-notify.jsonl"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.notifiers,
+            vec![
+                NotifierConfig::Jsonl(JsonlConfig { path: None }),
+                NotifierConfig::Jsonl(JsonlConfig {
+                    path: Some(String::from("/var/log/rss-notify.jsonl"))
+                }),
+            ]
+        );
+        assert_eq!(config.notifiers[0].kind(), "jsonl");
+    }
+
+    #[test]
+    fn test_templates_for_feed() {
+        let config: Config = toml::from_str(
+            r#"
+            [templates]
+            subject = "{{feed_title}}: {{latest_title}}"
+            body = "{{latest_summary_text}}"
+
+            [[feeds]]
+            link = "https://example.com/feed"
+            schedule = "* * * * *"
+            templates = { subject = "New release: {{latest_title}}" }
+
+            [[feeds]]
+            link = "https://example.com/other"
+            schedule = "* * * * *"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.templates_for(&["https://example.com/feed"]),
+            Templates {
+                subject: Some(String::from("New release: {{latest_title}}")),
+                body: Some(String::from("{{latest_summary_text}}")),
+            }
+        );
+        assert_eq!(
+            config.templates_for(&["https://example.com/other"]),
+            config.templates
+        );
+        assert_eq!(
+            config.templates_for(&["https://example.org/moved", "https://example.com/feed"]),
+            config.templates_for(&["https://example.com/feed"]),
+            "Found by an address the feed moved from"
+        );
+
+        let saved = toml::to_string_pretty(&Config::default()).unwrap();
+        assert!(!saved.contains("templates"), "Unset templates aren't saved");
+    }
+
+    #[test]
+    fn test_desktop_notifier_config() {
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [[notifiers]]
+            type = "desktop"
+            per = "item"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.notifiers,
+            vec![NotifierConfig::Desktop(DesktopConfig {
+                per: DesktopPer::Item,
+                max_items: 5,
+                images: true,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_exec_notifier_config() {
+        let config: Config = toml::from_str(
+            r#"
+            feeds = []
+
+            [[notifiers]]
+            type = "exec"
+            command = ["/usr/local/bin/fetch-episode", "--quiet"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.notifiers,
+            vec![NotifierConfig::Exec(ExecConfig {
+                command: vec![
+                    String::from("/usr/local/bin/fetch-episode"),
+                    String::from("--quiet")
+                ],
+                per: ExecPer::Item,
+                timeout_secs: 30,
+            })]
+        );
+    }
+
     #[test]
     fn test_clear_feeds() {
         let test_path = "./test-clear-feeds";
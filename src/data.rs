@@ -1,44 +1,259 @@
-use chrono::Local;
 use chrono::prelude::DateTime;
+use chrono::{FixedOffset, Local};
 use croner::Cron;
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::error::Error;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
+
+use crate::config::FetchConfig;
+use crate::error::{Error, Result};
 
 // url to feed
 pub type FeedLink = String;
 
-// date of last seen item from feed in rfc 2822 format
+// frequency is a cron expression, e.g. "0 * * * *"
+pub fn parse_frequency(frequency: &str) -> Result<Cron> {
+    Cron::from_str(frequency).map_err(|source| Error::Cron {
+        frequency: frequency.to_string(),
+        source,
+    })
+}
+
+// how many item guids/links are remembered per feed
+pub const MAX_SEEN_ITEMS: usize = 500;
+
+// http cache validators from the last response, sent back on the next fetch
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct HttpCache {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+// date of the newest item seen from the feed in rfc 2822 format
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct FeedLinkData {
     feed_link: FeedLink,
     frequency: String,
     last_seen: String,
+    // wall clock time of the last check in rfc 2822 format
+    #[serde(default)]
+    last_checked: String,
+    // guids (or links) of items already seen, oldest first
+    #[serde(default)]
+    seen_items: Vec<String>,
+    #[serde(default)]
+    http_cache: HttpCache,
+    // fetch failures in a row, reset by the next successful check
+    #[serde(default)]
+    consecutive_failures: u32,
+    #[serde(default)]
+    last_error: Option<String>,
+    // rfc 2822 time before which a failing feed shouldn't be fetched again
+    #[serde(default)]
+    next_attempt_after: Option<String>,
+    // muted feeds are still checked, so they stay current, but never notified
+    #[serde(default)]
+    muted: bool,
+    // guids (or links) of seen items to bring back as unseen after an rfc 2822 time
+    #[serde(default)]
+    snoozed_items: BTreeMap<String, String>,
+    // the feed's rel="self" link as of the last check, to notice it changing
+    #[serde(default)]
+    self_link: Option<String>,
+    // addresses the feed was added or known by before, oldest first, so
+    // settings kept under them still apply
+    #[serde(default)]
+    previous_links: Vec<String>,
 }
 
 impl FeedLinkData {
-    pub fn update_last_seen(&mut self) {
+    // Only ever moves forward, so an item dated in the past can't rewind it.
+    pub fn update_last_seen(&mut self, newest_item_date: DateTime<FixedOffset>) {
+        let current = DateTime::parse_from_rfc2822(&self.last_seen).ok();
+        if current.is_none_or(|current| newest_item_date > current) {
+            self.last_seen = newest_item_date.to_rfc2822();
+        }
+    }
+
+    pub fn update_last_checked(&mut self) {
         let date = Local::now().to_rfc2822();
-        self.last_seen = date
+        self.last_checked = date
+    }
+
+    pub fn is_item_seen(&self, id: &str) -> bool {
+        self.seen_items.iter().any(|seen| seen == id)
+    }
+
+    pub fn has_seen_items(&self) -> bool {
+        !self.seen_items.is_empty()
     }
 
-    pub fn is_frequency_check_due(&mut self) -> bool {
-        let cron = Cron::from_str(&self.frequency).expect("Should work....");
-        if self.last_seen.is_empty() {
-            self.update_last_seen();
+    // Moves the given ids to the back so the ones still present in the feed are
+    // never the ones trimmed off the front.
+    pub fn mark_items_seen(&mut self, ids: &[String]) {
+        self.seen_items.retain(|seen| !ids.contains(seen));
+        self.seen_items.extend(ids.iter().cloned());
+
+        let limit = MAX_SEEN_ITEMS.max(ids.len());
+        if self.seen_items.len() > limit {
+            let excess = self.seen_items.len() - limit;
+            self.seen_items.drain(..excess);
         }
-        let seen_date = DateTime::parse_from_rfc2822(&self.last_seen).unwrap();
-        let next = cron.find_next_occurrence(&seen_date, false).unwrap();
+    }
 
-        let now = Local::now();
+    pub fn seen_items(&self) -> &[String] {
+        &self.seen_items
+    }
+
+    pub fn snooze_item(&mut self, id: &str, until: DateTime<FixedOffset>) {
+        self.snoozed_items
+            .insert(String::from(id), until.to_rfc2822());
+    }
+
+    pub fn unsnooze_items(&mut self, ids: &[String]) {
+        self.snoozed_items.retain(|id, _| !ids.contains(id));
+    }
+
+    // An unreadable time counts as over, so the item isn't snoozed forever.
+    pub fn is_snooze_over(&self, id: &str) -> bool {
+        self.snoozed_items.get(id).is_some_and(|until| {
+            DateTime::parse_from_rfc2822(until)
+                .ok()
+                .is_none_or(|until| Local::now() >= until)
+        })
+    }
+
+    pub fn snoozes_over(&self) -> impl Iterator<Item = &str> {
+        self.snoozed_items
+            .keys()
+            .map(String::as_str)
+            .filter(|id| self.is_snooze_over(id))
+    }
+
+    pub fn has_snoozes_over(&self) -> bool {
+        self.snoozes_over().next().is_some()
+    }
+
+    pub fn is_snoozed(&self, id: &str) -> bool {
+        self.snoozed_items.contains_key(id)
+    }
+
+    pub fn self_link(&self) -> Option<&str> {
+        self.self_link.as_deref()
+    }
+
+    pub fn set_self_link(&mut self, self_link: Option<String>) {
+        self.self_link = self_link
+    }
+
+    pub fn add_previous_link(&mut self, link: &str) {
+        if link != self.feed_link && !self.previous_links.iter().any(|known| known == link) {
+            self.previous_links.push(String::from(link));
+        }
+    }
+
+    // The current address first, then the ones before it, newest first.
+    pub fn links(&self) -> Vec<&str> {
+        std::iter::once(self.feed_link.as_str())
+            .chain(self.previous_links.iter().rev().map(String::as_str))
+            .collect()
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted
+    }
+
+    pub fn http_cache(&self) -> &HttpCache {
+        &self.http_cache
+    }
+
+    pub fn set_http_cache(&mut self, http_cache: HttpCache) {
+        self.http_cache = http_cache
+    }
+
+    // Doubles the wait with every failure in a row, up to the configured max,
+    // and never retries sooner than the server's Retry-After.
+    pub fn record_failure(
+        &mut self,
+        error: &str,
+        retry_after: Option<Duration>,
+        config: &FetchConfig,
+    ) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error.to_string());
+
+        let doublings = (self.consecutive_failures - 1).min(16);
+        let backoff = Duration::from_secs(config.failure_backoff_base_secs)
+            .saturating_mul(1 << doublings)
+            .min(Duration::from_secs(config.failure_backoff_max_secs));
+        let wait = retry_after.map_or(backoff, |retry_after| retry_after.max(backoff));
+        let wait = chrono::Duration::from_std(wait).unwrap_or(chrono::Duration::MAX);
+
+        self.next_attempt_after = Local::now()
+            .checked_add_signed(wait)
+            .map(|next| next.to_rfc2822());
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.last_error = None;
+        self.next_attempt_after = None;
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
 
-        now >= next
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    // None when the feed has never been checked, meaning it's due right away.
+    // A failing feed isn't due until its backoff has passed as well.
+    pub fn next_check_due(&self) -> Result<Option<DateTime<FixedOffset>>> {
+        let backoff = self
+            .next_attempt_after
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+
+        Ok(self
+            .next_scheduled_check()?
+            .into_iter()
+            .chain(backoff)
+            .max())
+    }
+
+    fn next_scheduled_check(&self) -> Result<Option<DateTime<FixedOffset>>> {
+        let cron = parse_frequency(&self.frequency)?;
+        // data saved before last_checked existed kept the check time in last_seen
+        let checked = if self.last_checked.is_empty() {
+            &self.last_seen
+        } else {
+            &self.last_checked
+        };
+        let Ok(checked_date) = DateTime::parse_from_rfc2822(checked) else {
+            return Ok(None);
+        };
+
+        Ok(cron.find_next_occurrence(&checked_date, false).ok())
+    }
+
+    pub fn is_frequency_check_due(&self) -> Result<bool> {
+        Ok(match self.next_check_due()? {
+            Some(next) => Local::now() >= next,
+            None => true,
+        })
     }
 
     pub fn feed_link(&self) -> &str {
@@ -52,11 +267,16 @@ impl FeedLinkData {
     pub fn last_seen(&self) -> &str {
         &self.last_seen
     }
+
+    pub fn last_checked(&self) -> &str {
+        &self.last_checked
+    }
     pub fn new_for_testing(link: &str, last_seen: &str) -> Self {
         Self {
             feed_link: link.into(),
             frequency: "* * * * *".into(),
             last_seen: String::from(last_seen),
+            ..Default::default()
         }
     }
 
@@ -65,6 +285,7 @@ impl FeedLinkData {
             feed_link,
             frequency,
             last_seen,
+            ..Default::default()
         }
     }
 }
@@ -73,8 +294,8 @@ impl fmt::Display for FeedLinkData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{},\t{},\t{}",
-            self.feed_link, self.frequency, self.last_seen
+            "{},\t{},\t{},\t{}",
+            self.feed_link, self.frequency, self.last_seen, self.last_checked
         )
     }
 }
@@ -83,8 +304,52 @@ impl fmt::Display for FeedLinkData {
 pub struct Data {
     #[serde(default)]
     link_map: HashMap<FeedLink, FeedLinkData>,
+    // the queued actions applied by load, to be dropped once saved
+    #[serde(skip)]
+    claimed_actions: String,
+}
+
+// Changes asked for outside a check, like a click on a popup. They're queued in
+// actions.jsonl next to data.toml instead of saved straight away, so a daemon
+// holding its own copy of the data can't save over them: the next load applies
+// them and the save after it clears them.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum QueuedAction {
+    // until is rfc2822, like the snoozes in data.toml
+    Snooze {
+        feed_link: String,
+        id: String,
+        until: String,
+    },
+    MarkRead {
+        feed_link: String,
+        id: String,
+    },
+    Mute {
+        feed_link: String,
+    },
+}
+
+pub fn queue_action(path: Option<&str>, action: &QueuedAction) -> Result<()> {
+    let path = get_data_dir(path)?.join(ACTIONS_FILE);
+    let mut line = serde_json::to_string(action)
+        .map_err(|e| Error::config("Failed to serialize an action", e))?;
+    line.push('\n');
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| Error::io(&path, e))?;
+    file.write_all(line.as_bytes())
+        .map_err(|e| Error::io(&path, e))
 }
 
+const ACTIONS_FILE: &str = "actions.jsonl";
+// actions that have been loaded, but not yet saved into data.toml
+const CLAIMED_ACTIONS_FILE: &str = "actions.jsonl.claimed";
+
 impl Data {
     pub fn link_map(&self) -> &HashMap<FeedLink, FeedLinkData> {
         &self.link_map
@@ -93,18 +358,23 @@ impl Data {
         self.link_map.get(feed)
     }
 
+    // Sorted by link so checks always run and report in the same order.
     pub fn get_all_feed_link_data(&self) -> Vec<FeedLinkData> {
-        self.link_map.values().cloned().collect()
+        let mut feeds: Vec<FeedLinkData> = self.link_map.values().cloned().collect();
+        feeds.sort_by(|a, b| a.feed_link.cmp(&b.feed_link));
+        feeds
     }
 
     pub fn insert_link_map(&mut self, feed_link: &str, frequency: &str) -> Option<&FeedLinkData> {
         let mut feed_link_data = FeedLinkData {
             feed_link: String::from(feed_link),
             frequency: String::from(frequency),
-            last_seen: String::new(),
+            ..Default::default()
         };
 
-        feed_link_data.update_last_seen();
+        // Nothing has been fetched yet, so start the cursor from now.
+        feed_link_data.update_last_seen(Local::now().fixed_offset());
+        feed_link_data.update_last_checked();
 
         self.link_map
             .insert(String::from(feed_link), feed_link_data);
@@ -112,17 +382,117 @@ impl Data {
         self.link_map.get(feed_link)
     }
 
-    pub fn update_link_map(&mut self, feed: &str) -> Option<&FeedLinkData> {
+    pub fn update_link_map(
+        &mut self,
+        feed: &str,
+        item_ids: &[String],
+        newest_item_date: Option<DateTime<FixedOffset>>,
+    ) -> Option<&FeedLinkData> {
         let feed_link_data = self.link_map.get_mut(feed);
 
         if let Some(data) = feed_link_data {
-            data.update_last_seen();
+            if let Some(date) = newest_item_date {
+                data.update_last_seen(date);
+            }
+            data.update_last_checked();
+            data.record_success();
+            data.mark_items_seen(item_ids);
             self.link_map.get(feed)
         } else {
             None
         }
     }
 
+    pub fn update_http_cache(
+        &mut self,
+        feed: &str,
+        http_cache: HttpCache,
+    ) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.set_http_cache(http_cache);
+        Some(data)
+    }
+
+    pub fn record_failure(
+        &mut self,
+        feed: &str,
+        error: &str,
+        retry_after: Option<Duration>,
+        config: &FetchConfig,
+    ) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.record_failure(error, retry_after, config);
+        Some(data)
+    }
+
+    // Marks an item as read from a notification: seen, and no longer snoozed.
+    pub fn mark_item_read(&mut self, feed: &str, id: &str) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        let id = [String::from(id)];
+        data.mark_items_seen(&id);
+        data.unsnooze_items(&id);
+        Some(data)
+    }
+
+    pub fn snooze_item(
+        &mut self,
+        feed: &str,
+        id: &str,
+        until: DateTime<FixedOffset>,
+    ) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.snooze_item(id, until);
+        Some(data)
+    }
+
+    pub fn unsnooze_items(&mut self, feed: &str, ids: &[String]) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.unsnooze_items(ids);
+        Some(data)
+    }
+
+    pub fn add_previous_link(&mut self, feed: &str, previous: &str) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.add_previous_link(previous);
+        Some(data)
+    }
+
+    pub fn update_self_link(&mut self, feed: &str, self_link: &str) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.set_self_link(Some(String::from(self_link)));
+        Some(data)
+    }
+
+    pub fn set_muted(&mut self, feed: &str, muted: bool) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.set_muted(muted);
+        Some(data)
+    }
+
+    pub fn update_last_checked(&mut self, feed: &str) -> Option<&FeedLinkData> {
+        let data = self.link_map.get_mut(feed)?;
+        data.update_last_checked();
+        Some(data)
+    }
+
+    // Moves a feed's entry to its new address, keeping what's been seen. If the
+    // new address is already subscribed, that entry wins and the old one goes.
+    pub fn migrate_link_map(&mut self, old: &str, new: &str) -> Option<&FeedLinkData> {
+        let data = self.link_map.remove(old)?;
+        let moved = self
+            .link_map
+            .entry(String::from(new))
+            .or_insert_with(|| FeedLinkData {
+                feed_link: String::from(new),
+                previous_links: Vec::new(),
+                ..data.clone()
+            });
+        for link in data.links().into_iter().rev() {
+            moved.add_previous_link(link);
+        }
+        Some(moved)
+    }
+
     pub fn remove_link_map(&mut self, feed: &str) {
         self.link_map.remove(feed);
     }
@@ -135,62 +505,152 @@ impl Data {
         self.link_map.clear();
     }
 
-    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
-        let path = get_data_path(path);
+    pub fn apply_action(&mut self, action: &QueuedAction) {
+        match action {
+            QueuedAction::Snooze {
+                feed_link,
+                id,
+                until,
+            } => {
+                // An unreadable time brings the item back at the next check.
+                let until = DateTime::parse_from_rfc2822(until)
+                    .unwrap_or_else(|_| Local::now().fixed_offset());
+                self.snooze_item(feed_link, id, until);
+            }
+            QueuedAction::MarkRead { feed_link, id } => {
+                self.mark_item_read(feed_link, id);
+            }
+            QueuedAction::Mute { feed_link } => {
+                self.set_muted(feed_link, true);
+            }
+        }
+    }
 
-        if path.exists() {
-            let contents = fs::read_to_string(&path)?;
-            let data = toml::from_str(&contents)?;
-            Ok(data)
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let dir = get_data_dir(path)?;
+        let path = get_data_path(path)?;
+
+        let mut data: Data = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+            toml::from_str(&contents)
+                .map_err(|e| Error::config(format!("Invalid data file {}", path.display()), e))?
         } else {
             let data = Data::default();
             create_data(&path, &data)?;
-            Ok(data)
+            data
+        };
+
+        let claimed = claim_actions(&dir)?;
+        for line in claimed.lines() {
+            match serde_json::from_str(line) {
+                Ok(action) => data.apply_action(&action),
+                Err(e) => eprintln!("Skipping a queued action: {}", e),
+            }
         }
+        data.claimed_actions = claimed;
+        Ok(data)
     }
 
-    pub fn save(&self, path: Option<&str>) -> Result<(), Box<dyn Error>> {
-        let path = get_data_path(path);
+    pub fn save(&self, path: Option<&str>) -> Result<()> {
+        let dir = get_data_dir(path)?;
+        let path = get_data_path(path)?;
 
         if path.exists() {
-            let toml_file = toml::to_string_pretty(self)?;
-            fs::write(&path, toml_file)?;
+            write_toml(&path, self)?;
         } else {
             create_data(&path, self)?;
         }
-        Ok(())
+        release_actions(&dir, &self.claimed_actions)
+    }
+}
+
+// Moves newly queued actions behind any already claimed, and returns them all.
+// The rename comes first so a popup queueing meanwhile starts a new file.
+fn claim_actions(dir: &Path) -> Result<String> {
+    let queued = dir.join(ACTIONS_FILE);
+    let claimed = dir.join(CLAIMED_ACTIONS_FILE);
+    let moving = dir.join(format!("{}.{}", ACTIONS_FILE, std::process::id()));
+
+    let mut actions = read_if_exists(&claimed)?;
+    match fs::rename(&queued, &moving) {
+        Ok(()) => {
+            actions.push_str(&read_if_exists(&moving)?);
+            fs::write(&claimed, &actions).map_err(|e| Error::io(&claimed, e))?;
+            fs::remove_file(&moving).map_err(|e| Error::io(&moving, e))?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::io(&queued, e)),
+    }
+    Ok(actions)
+}
+
+// Drops the claimed actions that are now saved in data.toml, keeping any
+// another process claimed since.
+fn release_actions(dir: &Path, saved: &str) -> Result<()> {
+    let claimed = dir.join(CLAIMED_ACTIONS_FILE);
+    let actions = read_if_exists(&claimed)?;
+    match actions.strip_prefix(saved) {
+        Some("") => match fs::remove_file(&claimed) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::io(&claimed, e)),
+            _ => Ok(()),
+        },
+        Some(rest) if !saved.is_empty() => {
+            fs::write(&claimed, rest).map_err(|e| Error::io(&claimed, e))
+        }
+        _ => Ok(()),
     }
 }
 
-fn get_data_path(path: Option<&str>) -> PathBuf {
-    if let Some(p) = path {
-        let dir = PathBuf::from(p);
-        fs::create_dir_all(&dir).expect("Failed to create data directory.");
-        return dir.join("data.toml");
+fn read_if_exists(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(Error::io(path, e)),
     }
+}
 
-    let dirs = ProjectDirs::from("com", "martinezjandrew", "rss-notify")
-        .expect("Unable to get data directory.");
+fn get_data_path(path: Option<&str>) -> Result<PathBuf> {
+    Ok(get_data_dir(path)?.join("data.toml"))
+}
 
-    let data_dir = dirs.data_dir();
-    fs::create_dir_all(data_dir).expect("Failed to create config directory.");
+// Where data.toml and anything else kept between runs live, created if needed.
+pub fn get_data_dir(path: Option<&str>) -> Result<PathBuf> {
+    let data_dir = match path {
+        Some(p) => PathBuf::from(p),
+        None => ProjectDirs::from("com", "martinezjandrew", "rss-notify")
+            .ok_or_else(|| Error::Config {
+                reason: "Unable to find a data directory".to_string(),
+                source: None,
+            })?
+            .data_dir()
+            .to_path_buf(),
+    };
 
-    data_dir.join("data.toml")
+    fs::create_dir_all(&data_dir).map_err(|e| Error::io(&data_dir, e))?;
+    Ok(data_dir)
 }
 
-fn create_data(path: &Path, data: &Data) -> Result<PathBuf, Box<dyn Error>> {
+fn create_data(path: &Path, data: &Data) -> Result<PathBuf> {
     if path.exists() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
-            "Config path already exists.",
-        )));
+        return Err(Error::io(
+            path,
+            io::Error::new(io::ErrorKind::AlreadyExists, "Data path already exists."),
+        ));
     }
 
-    let toml_str = toml::to_string_pretty(&data)?;
-    fs::write(path, toml_str)?;
+    write_toml(path, data)?;
     Ok(path.to_path_buf())
 }
 
+// Written aside and renamed, so nothing ever reads half a file.
+fn write_toml(path: &Path, data: &Data) -> Result<()> {
+    let toml_str = toml::to_string_pretty(data)
+        .map_err(|e| Error::config(format!("Failed to serialize {}", path.display()), e))?;
+    let partial = path.with_extension(format!("toml.{}", std::process::id()));
+    fs::write(&partial, toml_str).map_err(|e| Error::io(&partial, e))?;
+    fs::rename(&partial, path).map_err(|e| Error::io(path, e))
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::Days;
@@ -200,7 +660,7 @@ mod tests {
     #[test]
     fn test_temp_data_path() {
         let data_path = "./test-temp-data-path";
-        let path = get_data_path(Some(data_path));
+        let path = get_data_path(Some(data_path)).unwrap();
         assert!(path.ends_with("data.toml"));
 
         std::fs::remove_dir_all(data_path).ok();
@@ -283,18 +743,353 @@ mod tests {
         std::fs::remove_dir_all(path).ok();
     }
 
+    #[test]
+    fn test_update_link_map_marks_items_seen() {
+        let path = "./test-update-link-map-marks-items-seen";
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+
+        let _ = data.insert_link_map("https://test/", "* * 10 * *");
+        let ids = vec![String::from("guid-1"), String::from("guid-2")];
+        data.update_link_map("https://test/", &ids, None);
+        data.save(Some(path)).expect("failed to save data");
+
+        let data2: Data = Data::load(Some(path)).expect("Failed to load or create data");
+        let feed = data2.get_link_map("https://test/").unwrap();
+        assert!(feed.is_item_seen("guid-1"));
+        assert!(feed.is_item_seen("guid-2"));
+        assert!(!feed.is_item_seen("guid-3"));
+
+        std::fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn test_update_link_map_uses_item_dates() {
+        let path = "./test-update-link-map-uses-item-dates";
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+
+        let _ = data.insert_link_map("https://test/", "* * 10 * *");
+        let added = data
+            .get_link_map("https://test/")
+            .unwrap()
+            .last_seen()
+            .to_string();
+
+        // An item dated before the feed was added shouldn't rewind the cursor.
+        let old = DateTime::parse_from_rfc2822("Wed, 20 Nov 2024 10:00:00 +0000").unwrap();
+        let feed = data
+            .update_link_map("https://test/", &[], Some(old))
+            .unwrap();
+        assert_eq!(feed.last_seen(), added);
+
+        let future = Local::now().fixed_offset() + chrono::Duration::hours(1);
+        let feed = data
+            .update_link_map("https://test/", &[], Some(future))
+            .unwrap();
+        assert_eq!(feed.last_seen(), future.to_rfc2822());
+        assert!(!feed.last_checked().is_empty());
+        assert_ne!(feed.last_seen(), feed.last_checked());
+
+        std::fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn test_http_cache_round_trip() {
+        let path = "./test-http-cache-round-trip";
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+
+        let _ = data.insert_link_map("https://test/", "* * 10 * *");
+        data.save(Some(path)).expect("failed to save data");
+        let data2: Data = Data::load(Some(path)).expect("Failed to load or create data");
+        assert_eq!(
+            data2.get_link_map("https://test/").unwrap().http_cache(),
+            &HttpCache::default()
+        );
+
+        let cache = HttpCache {
+            etag: Some(String::from("\"abc\"")),
+            last_modified: Some(String::from("Wed, 20 Nov 2024 10:00:00 GMT")),
+        };
+        data.update_http_cache("https://test/", cache.clone());
+        data.save(Some(path)).expect("failed to save data");
+
+        let data3: Data = Data::load(Some(path)).expect("Failed to load or create data");
+        assert_eq!(
+            data3.get_link_map("https://test/").unwrap().http_cache(),
+            &cache
+        );
+
+        std::fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn test_migrate_link_map() {
+        let path = "./test-migrate-link-map";
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+
+        let _ = data.insert_link_map("https://old/", "* * 10 * *");
+        data.update_link_map("https://old/", &[String::from("guid-1")], None);
+
+        let feed = data
+            .migrate_link_map("https://old/", "https://new/")
+            .unwrap();
+        assert_eq!(feed.feed_link(), "https://new/");
+        assert_eq!(feed.frequency(), "* * 10 * *");
+        assert!(feed.is_item_seen("guid-1"), "Seen state should move too");
+        assert_eq!(feed.links(), vec!["https://new/", "https://old/"]);
+        assert_eq!(data.get_feeds(), vec![String::from("https://new/")]);
+
+        let feed = data
+            .migrate_link_map("https://new/", "https://newer/")
+            .unwrap();
+        assert_eq!(
+            feed.links(),
+            vec!["https://newer/", "https://new/", "https://old/"]
+        );
+        let feed = data
+            .migrate_link_map("https://newer/", "https://old/")
+            .unwrap();
+        assert_eq!(
+            feed.links(),
+            vec!["https://old/", "https://newer/", "https://new/"],
+            "Moving back doesn't list an address twice"
+        );
+
+        assert!(
+            data.migrate_link_map("https://missing/", "https://new/")
+                .is_none()
+        );
+
+        std::fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn test_mark_items_seen_is_bounded() {
+        let mut feed = FeedLinkData::new_for_testing("https://test.com/", "");
+
+        let old: Vec<String> = (0..MAX_SEEN_ITEMS).map(|i| format!("old-{}", i)).collect();
+        feed.mark_items_seen(&old);
+
+        let current = vec![String::from("old-0"), String::from("new-0")];
+        feed.mark_items_seen(&current);
+
+        assert_eq!(feed.seen_items().len(), MAX_SEEN_ITEMS);
+        assert!(feed.is_item_seen("old-0"), "Re-marked ids should be kept");
+        assert!(feed.is_item_seen("new-0"));
+        assert!(!feed.is_item_seen("old-1"), "Oldest id should be dropped");
+    }
+
+    #[test]
+    fn test_snooze_mark_read_and_mute() {
+        let path = "./test-snooze-and-mute";
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+
+        let _ = data.insert_link_map("https://test/", "* * 10 * *");
+        data.update_link_map("https://test/", &[String::from("guid-1")], None);
+        let now = Local::now().fixed_offset();
+        data.snooze_item("https://test/", "guid-1", now + chrono::Duration::hours(1));
+        data.snooze_item(
+            "https://test/",
+            "guid-2",
+            now - chrono::Duration::minutes(1),
+        );
+        data.set_muted("https://test/", true);
+        data.save(Some(path)).expect("failed to save data");
+
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+        let feed = data.get_link_map("https://test/").unwrap();
+        assert!(feed.is_muted());
+        assert!(feed.is_snoozed("guid-1"));
+        assert!(!feed.is_snooze_over("guid-1"), "Snoozed for another hour");
+        assert!(feed.is_snooze_over("guid-2"));
+        assert!(!feed.is_snooze_over("guid-3"), "Never snoozed");
+
+        let feed = data.mark_item_read("https://test/", "guid-1").unwrap();
+        assert!(!feed.is_snoozed("guid-1"));
+        assert!(feed.is_item_seen("guid-1"));
+
+        let feed = data
+            .unsnooze_items("https://test/", &[String::from("guid-2")])
+            .unwrap();
+        assert!(!feed.is_snoozed("guid-2"));
+
+        assert!(data.mark_item_read("https://missing/", "guid-1").is_none());
+
+        std::fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn test_queued_actions_survive_a_stale_save() {
+        let path = "./test-queued-actions";
+        let mut data = Data::load(Some(path)).unwrap();
+        data.insert_link_map("https://test/", "* * * * *");
+        data.save(Some(path)).unwrap();
+
+        // A daemon loads, a popup is answered, then the daemon saves its copy.
+        let daemon = Data::load(Some(path)).unwrap();
+        let until = (Local::now() + chrono::Duration::hours(1)).to_rfc2822();
+        queue_action(
+            Some(path),
+            &QueuedAction::Snooze {
+                feed_link: String::from("https://test/"),
+                id: String::from("guid-1"),
+                until,
+            },
+        )
+        .unwrap();
+        daemon.save(Some(path)).unwrap();
+
+        // Claimed by a load that never saves, then queued on top of that.
+        let listing = Data::load(Some(path)).unwrap();
+        assert!(
+            listing
+                .get_link_map("https://test/")
+                .unwrap()
+                .is_snoozed("guid-1")
+        );
+        queue_action(
+            Some(path),
+            &QueuedAction::Mute {
+                feed_link: String::from("https://test/"),
+            },
+        )
+        .unwrap();
+
+        let data = Data::load(Some(path)).unwrap();
+        let feed = data.get_link_map("https://test/").unwrap();
+        assert!(feed.is_snoozed("guid-1"));
+        assert!(feed.is_muted());
+        data.save(Some(path)).unwrap();
+        assert!(!Path::new(path).join(ACTIONS_FILE).exists());
+        assert!(!Path::new(path).join(CLAIMED_ACTIONS_FILE).exists());
+
+        let mut data = Data::load(Some(path)).unwrap();
+        assert!(data.get_link_map("https://test/").unwrap().is_muted());
+        data.set_muted("https://test/", false);
+        data.save(Some(path)).unwrap();
+        let data = Data::load(Some(path)).unwrap();
+        assert!(
+            !data.get_link_map("https://test/").unwrap().is_muted(),
+            "Saved actions aren't applied again"
+        );
+
+        std::fs::remove_dir_all(path).ok();
+    }
+
     #[test]
     fn test_if_time_to_check() {
         let now = Local::now();
         let sample_last_seen = now.checked_sub_days(Days::new(10));
-        let mut sample = FeedLinkData {
+        let sample = FeedLinkData {
             feed_link: String::from("https://test.com/"),
             frequency: String::from("* * 10 * *"),
-            last_seen: sample_last_seen.unwrap().to_rfc2822(),
+            last_seen: String::new(),
+            last_checked: sample_last_seen.unwrap().to_rfc2822(),
+            ..Default::default()
         };
         assert!(
-            sample.is_frequency_check_due(),
+            sample.is_frequency_check_due().unwrap(),
             "Today is 10 days from 10 days ago..."
         );
     }
+
+    #[test]
+    fn test_not_time_to_check() {
+        let sample = FeedLinkData {
+            feed_link: String::from("https://test.com/"),
+            frequency: String::from("0 0 1 1 *"),
+            last_checked: Local::now().to_rfc2822(),
+            ..Default::default()
+        };
+        assert!(
+            !sample.is_frequency_check_due().unwrap(),
+            "Checked just now, next check is next January"
+        );
+        assert!(sample.next_check_due().unwrap().unwrap() > Local::now());
+    }
+
+    #[test]
+    fn test_failures_back_off() {
+        let path = "./test-failures-back-off";
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+        let config = FetchConfig {
+            failure_backoff_base_secs: 60,
+            failure_backoff_max_secs: 3600,
+            ..Default::default()
+        };
+
+        let _ = data.insert_link_map("https://test/", "* * * * *");
+        let feed = data
+            .record_failure("https://test/", "HTTP status 500", None, &config)
+            .unwrap();
+        let first_wait = feed.next_check_due().unwrap().unwrap() - Local::now().fixed_offset();
+        assert_eq!(feed.consecutive_failures(), 1);
+        assert_eq!(feed.last_error(), Some("HTTP status 500"));
+        assert!(
+            !feed.is_frequency_check_due().unwrap(),
+            "Should be backing off"
+        );
+
+        let feed = data
+            .record_failure("https://test/", "HTTP status 500", None, &config)
+            .unwrap();
+        let second_wait = feed.next_check_due().unwrap().unwrap() - Local::now().fixed_offset();
+        assert!(second_wait > first_wait, "Backoff should grow");
+
+        let feed = data
+            .record_failure(
+                "https://test/",
+                "HTTP status 429",
+                Some(Duration::from_secs(7200)),
+                &config,
+            )
+            .unwrap();
+        let retry_after_wait =
+            feed.next_check_due().unwrap().unwrap() - Local::now().fixed_offset();
+        assert!(
+            retry_after_wait > chrono::Duration::seconds(3600),
+            "Retry-After beats the max backoff"
+        );
+
+        data.save(Some(path)).expect("failed to save data");
+        let mut data: Data = Data::load(Some(path)).expect("Failed to load or create data");
+        assert_eq!(
+            data.get_link_map("https://test/")
+                .unwrap()
+                .consecutive_failures(),
+            3
+        );
+
+        let feed = data.update_link_map("https://test/", &[], None).unwrap();
+        assert_eq!(feed.consecutive_failures(), 0);
+        assert!(feed.last_error().is_none());
+
+        std::fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn test_never_checked_is_due() {
+        let sample = FeedLinkData::new_for_testing("https://test.com/", "");
+        assert!(sample.next_check_due().unwrap().is_none());
+        assert!(sample.is_frequency_check_due().unwrap());
+    }
+
+    #[test]
+    fn test_parse_frequency() {
+        assert!(parse_frequency("* * * * *").is_ok());
+        assert!(parse_frequency("0 */6 * * *").is_ok());
+        assert!(parse_frequency("every hour").is_err());
+        assert!(parse_frequency("61 * * * *").is_err());
+        assert!(parse_frequency("").is_err());
+    }
+
+    #[test]
+    fn test_bad_frequency_is_an_error() {
+        let mut sample = FeedLinkData::new_for_testing("https://test.com/", "");
+        sample.frequency = String::from("every hour");
+
+        assert!(matches!(
+            sample.is_frequency_check_due(),
+            Err(Error::Cron { .. })
+        ));
+    }
 }
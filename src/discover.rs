@@ -0,0 +1,191 @@
+use reqwest::Url;
+
+// <link rel="alternate" type="..."> types that point at something we can parse.
+const FEED_TYPES: [&str; 3] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+];
+
+pub fn is_html(content: &[u8], content_type: Option<&str>) -> bool {
+    if content_type.is_some_and(|mime| mime.contains("html")) {
+        return true;
+    }
+
+    let start = String::from_utf8_lossy(&content[..content.len().min(512)]).to_lowercase();
+    let start = start.trim_start();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+// Feed links advertised in an HTML page's <link rel="alternate"> tags, resolved
+// against the page's address, in the order the page lists them.
+pub fn discover_feed_links(html: &str, base: &Url) -> Vec<String> {
+    let mut links = Vec::new();
+
+    for attributes in link_tags(html) {
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let is_alternate =
+            attribute("rel").is_some_and(|rel| rel.split_whitespace().any(|r| r == "alternate"));
+        let is_feed = attribute("type")
+            .is_some_and(|mime| FEED_TYPES.contains(&mime.trim().to_lowercase().as_str()));
+        let Some(href) = attribute("href") else {
+            continue;
+        };
+
+        if is_alternate
+            && is_feed
+            && let Ok(url) = base.join(href.trim())
+        {
+            let url = url.to_string();
+            if !links.contains(&url) {
+                links.push(url);
+            }
+        }
+    }
+
+    links
+}
+
+// Attributes (lowercased names) of every <link> tag. Not a full HTML parser,
+// but enough for the <head> of real pages.
+fn link_tags(html: &str) -> Vec<Vec<(String, String)>> {
+    let lower = html.to_ascii_lowercase();
+    let mut tags = Vec::new();
+    let mut rest = 0;
+
+    while let Some(found) = lower[rest..].find("<link") {
+        let start = rest + found + "<link".len();
+        rest = start;
+
+        let next = lower[start..].chars().next();
+        if !next.is_some_and(|c| c.is_ascii_whitespace() || c == '/' || c == '>') {
+            continue;
+        }
+
+        let (attributes, end) = parse_attributes(html, start);
+        tags.push(attributes);
+        rest = end;
+    }
+
+    tags
+}
+
+fn parse_attributes(html: &str, start: usize) -> (Vec<(String, String)>, usize) {
+    let bytes = html.as_bytes();
+    let mut attributes = Vec::new();
+    let mut i = start;
+
+    loop {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        if i >= bytes.len() || bytes[i] == b'>' {
+            return (attributes, i);
+        }
+
+        let name_start = i;
+        while i < bytes.len()
+            && !bytes[i].is_ascii_whitespace()
+            && !matches!(bytes[i], b'=' | b'>' | b'/')
+        {
+            i += 1;
+        }
+        let name = html[name_start..i].to_ascii_lowercase();
+
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= bytes.len() || bytes[i] != b'=' {
+            attributes.push((name, String::new()));
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        let value = match bytes.get(i) {
+            Some(quote @ (b'"' | b'\'')) => {
+                let value_start = i + 1;
+                let value_end = html[value_start..]
+                    .find(*quote as char)
+                    .map_or(bytes.len(), |end| value_start + end);
+                i = (value_end + 1).min(bytes.len());
+                &html[value_start..value_end]
+            }
+            _ => {
+                let value_start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                    i += 1;
+                }
+                &html[value_start..i]
+            }
+        };
+        attributes.push((name, decode_entities(value)));
+    }
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <link rel="stylesheet" href="/style.css">
+  <LINK REL="alternate" TYPE="application/rss+xml" TITLE="News" HREF="/feeds/news/">
+  <link rel='alternate' type='application/atom+xml' href='https://example.com/atom.xml?a=1&amp;b=2' />
+  <link rel="alternate" type="text/html" hreflang="es" href="/es/">
+  <link type="application/feed+json" rel="alternate home" href=feed.json>
+  <link rel="alternate" type="application/rss+xml" href="/feeds/news/">
+  <linkage rel="alternate" type="application/rss+xml" href="/not-a-link-tag">
+</head>
+<body></body>
+</html>"#;
+
+    #[test]
+    fn test_discover_feed_links() {
+        let base = Url::parse("https://example.com/news/").unwrap();
+        let links = discover_feed_links(PAGE, &base);
+
+        assert_eq!(
+            links,
+            vec![
+                "https://example.com/feeds/news/",
+                "https://example.com/atom.xml?a=1&b=2",
+                "https://example.com/news/feed.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_discover_without_feed_links() {
+        let base = Url::parse("https://example.com/").unwrap();
+        let page = r#"<html><head><link rel="icon" href="/favicon.ico"></head></html>"#;
+
+        assert!(discover_feed_links(page, &base).is_empty());
+    }
+
+    #[test]
+    fn test_is_html() {
+        assert!(is_html(PAGE.as_bytes(), None));
+        assert!(is_html(b"whatever", Some("text/html; charset=utf-8")));
+        assert!(!is_html(b"<?xml version=\"1.0\"?><rss/>", None));
+        assert!(!is_html(b"<rss/>", Some("application/rss+xml")));
+    }
+}
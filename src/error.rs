@@ -0,0 +1,176 @@
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use std::fmt::Write;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Messages describe what went wrong at this level only; the underlying cause is
+// left in source(), and report() joins the whole chain for display.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // the request never got a usable response: bad link, connection, timeout,
+    // too many redirects, oversized body, or a web page without a feed
+    #[error("Failed to fetch {url}: {reason}")]
+    Fetch {
+        url: String,
+        reason: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    #[error("{url} responded with HTTP status {status}")]
+    HttpStatus {
+        url: String,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    #[error("Failed to parse {format} feed")]
+    Parse {
+        format: &'static str,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Failed to access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    // config.toml and data.toml, whether unreadable, unwritable or just wrong
+    #[error("{reason}")]
+    Config {
+        reason: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    #[error("Invalid frequency \"{frequency}\"")]
+    Cron {
+        frequency: String,
+        #[source]
+        source: croner::errors::CronError,
+    },
+    #[error("Failed to send notification through {notifier}")]
+    Notify {
+        notifier: String,
+        #[source]
+        source: BoxError,
+    },
+}
+
+impl Error {
+    pub(crate) fn fetch(url: &str, reason: impl Into<String>) -> Self {
+        Error::Fetch {
+            url: url.to_string(),
+            reason: reason.into(),
+            source: None,
+        }
+    }
+
+    pub(crate) fn request(url: &str, source: reqwest::Error) -> Self {
+        let reason = if source.is_timeout() {
+            "timed out"
+        } else if source.is_connect() {
+            "couldn't connect"
+        } else {
+            "request failed"
+        };
+        Error::Fetch {
+            url: url.to_string(),
+            reason: reason.to_string(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn config(reason: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        Error::Config {
+            reason: reason.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub(crate) fn notify(notifier: &str, source: impl Into<BoxError>) -> Self {
+        Error::Notify {
+            notifier: notifier.to_string(),
+            source: source.into(),
+        }
+    }
+
+    // Timeouts, failed connections, 5xx and 429 are worth another try.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::HttpStatus { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Error::Fetch {
+                source: Some(source),
+                ..
+            } => source
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_timeout() || e.is_connect()),
+            _ => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::HttpStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    // The message followed by each of its causes, e.g.
+    // "Failed to parse RSS feed: unexpected end of input".
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = self.source();
+        while let Some(cause) = source {
+            let _ = write!(report, ": {}", cause);
+            source = cause.source();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_includes_sources() {
+        let error = Error::config(
+            "Invalid config file config.toml",
+            io::Error::other("missing field `feeds`"),
+        );
+
+        assert_eq!(error.to_string(), "Invalid config file config.toml");
+        assert_eq!(
+            error.report(),
+            "Invalid config file config.toml: missing field `feeds`"
+        );
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let status = |code| Error::HttpStatus {
+            url: "https://example.com/".into(),
+            status: StatusCode::from_u16(code).unwrap(),
+            retry_after: None,
+        };
+
+        assert!(status(503).is_transient());
+        assert!(status(429).is_transient());
+        assert!(!status(404).is_transient());
+        assert!(!Error::fetch("https://example.com/", "too many redirects").is_transient());
+    }
+}
@@ -0,0 +1,601 @@
+use atom_syndication::{Entry, Link};
+use chrono::{DateTime, FixedOffset};
+use rss::{Channel, Item};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+
+// Format-agnostic view of a feed, so the rest of the crate doesn't care
+// whether it came from an RSS channel, an Atom feed or a JSON Feed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Feed {
+    pub title: String,
+    pub link: Option<String>,
+    // where the feed says it lives (rel="self" / feed_url)
+    pub self_link: Option<String>,
+    // the feed's own image (RSS <image>, Atom <icon>/<logo>, JSON Feed icon)
+    pub icon: Option<String>,
+    pub items: Vec<FeedItem>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeedItem {
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub id: Option<String>,
+    pub published: Option<DateTime<FixedOffset>>,
+    pub updated: Option<DateTime<FixedOffset>>,
+    pub summary: Option<String>,
+    pub author: Option<String>,
+    pub categories: Vec<String>,
+    // an image enclosure or media:thumbnail, if the item has one
+    pub image: Option<String>,
+}
+
+impl Feed {
+    pub fn read_from(content: &[u8]) -> Result<Self> {
+        if looks_like_json(content) {
+            return Self::read_json(content);
+        }
+
+        match Channel::read_from(content) {
+            Ok(channel) => Ok(Feed::from(channel)),
+            // The root element isn't <rss>, so give Atom a go before giving up.
+            Err(rss::Error::InvalidStartTag) => {
+                let feed =
+                    atom_syndication::Feed::read_from(content).map_err(|e| Error::Parse {
+                        format: "Atom",
+                        source: Box::new(e),
+                    })?;
+                Ok(Feed::from(feed))
+            }
+            Err(e) => Err(Error::Parse {
+                format: "RSS",
+                source: Box::new(e),
+            }),
+        }
+    }
+
+    // Trusts a JSON content type, otherwise falls back to sniffing the body.
+    pub fn read_with_content_type(content: &[u8], content_type: Option<&str>) -> Result<Self> {
+        match content_type {
+            Some(mime) if mime.contains("json") => Self::read_json(content),
+            _ => Self::read_from(content),
+        }
+    }
+
+    pub fn read_json(content: &[u8]) -> Result<Self> {
+        let feed: JsonFeed = serde_json::from_slice(content).map_err(|e| Error::Parse {
+            format: "JSON",
+            source: Box::new(e),
+        })?;
+        Ok(Feed::from(feed))
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn items(&self) -> &[FeedItem] {
+        &self.items
+    }
+}
+
+impl FeedItem {
+    // Published date when the feed gives one, otherwise the last update.
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.published.or(self.updated)
+    }
+
+    // What we remember an item by: its guid/id, otherwise its link.
+    pub fn identity(&self) -> Option<&str> {
+        self.id.as_deref().or(self.link.as_deref())
+    }
+}
+
+impl From<Channel> for Feed {
+    fn from(channel: Channel) -> Self {
+        let self_link = channel.atom_ext().and_then(|atom| self_link(atom.links()));
+
+        Feed {
+            title: channel.title().to_string(),
+            link: non_empty(channel.link()),
+            self_link,
+            icon: channel.image().and_then(|image| non_empty(image.url())),
+            items: channel.items().iter().map(FeedItem::from).collect(),
+        }
+    }
+}
+
+impl From<&Item> for FeedItem {
+    fn from(item: &Item) -> Self {
+        FeedItem {
+            title: item.title().map(String::from),
+            link: item.link().map(String::from),
+            id: item.guid().map(|guid| guid.value().to_string()),
+            published: item
+                .pub_date()
+                .and_then(|date| DateTime::parse_from_rfc2822(date.trim()).ok()),
+            updated: None,
+            summary: item.description().map(String::from),
+            author: item
+                .author()
+                .and_then(non_empty)
+                .or_else(|| non_empty(item.dublin_core_ext()?.creators().first()?)),
+            categories: item
+                .categories()
+                .iter()
+                .filter_map(|category| non_empty(category.name()))
+                .collect(),
+            image: item
+                .enclosure()
+                .filter(|enclosure| enclosure.mime_type().starts_with("image/"))
+                .and_then(|enclosure| non_empty(enclosure.url()))
+                .or_else(|| media_image(item.extensions().get("media")?)),
+        }
+    }
+}
+
+impl From<atom_syndication::Feed> for Feed {
+    fn from(feed: atom_syndication::Feed) -> Self {
+        Feed {
+            title: feed.title().value.clone(),
+            link: alternate_link(feed.links()),
+            self_link: self_link(feed.links()),
+            icon: feed.icon().or(feed.logo()).and_then(non_empty),
+            items: feed.entries().iter().map(FeedItem::from).collect(),
+        }
+    }
+}
+
+impl From<&Entry> for FeedItem {
+    fn from(entry: &Entry) -> Self {
+        // atom_syndication fills a missing <updated> with the unix epoch.
+        let updated = Some(*entry.updated()).filter(|date| date.timestamp() != 0);
+        let summary = entry
+            .summary()
+            .map(|text| text.value.clone())
+            .or_else(|| entry.content().and_then(|c| c.value()).map(String::from));
+
+        FeedItem {
+            title: non_empty(&entry.title().value),
+            link: alternate_link(entry.links()),
+            id: non_empty(entry.id()),
+            published: entry.published().copied(),
+            updated,
+            summary,
+            author: entry
+                .authors()
+                .first()
+                .and_then(|person| non_empty(person.name())),
+            categories: entry
+                .categories()
+                .iter()
+                .filter_map(|category| non_empty(category.label().unwrap_or(category.term())))
+                .collect(),
+            image: entry
+                .links()
+                .iter()
+                .find(|link| {
+                    link.rel() == "enclosure"
+                        && link
+                            .mime_type()
+                            .is_some_and(|mime| mime.starts_with("image/"))
+                })
+                .and_then(|link| non_empty(link.href()))
+                .or_else(|| media_image(entry.extensions().get("media")?)),
+        }
+    }
+}
+
+// https://www.jsonfeed.org/version/1.1/
+#[derive(Debug, Deserialize)]
+struct JsonFeed {
+    // Never read, but being required keeps arbitrary JSON from parsing as a feed.
+    #[allow(dead_code)]
+    version: String,
+    title: String,
+    home_page_url: Option<String>,
+    feed_url: Option<String>,
+    icon: Option<String>,
+    favicon: Option<String>,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedItem {
+    // The spec asks readers to coerce non-string ids into strings.
+    id: Option<serde_json::Value>,
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    summary: Option<String>,
+    content_text: Option<String>,
+    content_html: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    image: Option<String>,
+    banner_image: Option<String>,
+    // 1.1 has a list of authors, 1.0 a single one
+    #[serde(default)]
+    authors: Vec<JsonFeedAuthor>,
+    author: Option<JsonFeedAuthor>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedAuthor {
+    name: Option<String>,
+}
+
+impl From<JsonFeed> for Feed {
+    fn from(feed: JsonFeed) -> Self {
+        Feed {
+            title: feed.title,
+            link: feed.home_page_url,
+            self_link: feed.feed_url,
+            icon: feed.icon.or(feed.favicon),
+            items: feed.items.into_iter().map(FeedItem::from).collect(),
+        }
+    }
+}
+
+impl From<JsonFeedItem> for FeedItem {
+    fn from(item: JsonFeedItem) -> Self {
+        let id = item.id.and_then(|id| match id {
+            serde_json::Value::String(s) => non_empty(&s),
+            serde_json::Value::Null => None,
+            other => Some(other.to_string()),
+        });
+        let parse_date =
+            |date: Option<String>| date.and_then(|d| DateTime::parse_from_rfc3339(d.trim()).ok());
+
+        FeedItem {
+            title: item.title,
+            link: item.url.or(item.external_url),
+            id,
+            published: parse_date(item.date_published),
+            updated: parse_date(item.date_modified),
+            summary: item.summary.or(item.content_text).or(item.content_html),
+            image: item.image.or(item.banner_image),
+            author: item
+                .authors
+                .into_iter()
+                .chain(item.author)
+                .find_map(|author| author.name),
+            categories: item.tags,
+        }
+    }
+}
+
+fn looks_like_json(content: &[u8]) -> bool {
+    content
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| *b == b'{')
+}
+
+fn alternate_link(links: &[Link]) -> Option<String> {
+    links
+        .iter()
+        .find(|link| link.rel() == "alternate")
+        .or_else(|| links.iter().find(|link| link.rel() != "self"))
+        .map(|link| link.href().to_string())
+}
+
+fn self_link(links: &[Link]) -> Option<String> {
+    links
+        .iter()
+        .find(|link| link.rel() == "self")
+        .and_then(|link| non_empty(link.href()))
+}
+
+// The rss and atom_syndication extension types have the same shape, but aren't
+// the same type.
+trait MediaExtension: Sized {
+    fn attr(&self, name: &str) -> Option<&str>;
+    fn children(&self) -> &BTreeMap<String, Vec<Self>>;
+}
+
+impl MediaExtension for rss::extension::Extension {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs().get(name).map(String::as_str)
+    }
+
+    fn children(&self) -> &BTreeMap<String, Vec<Self>> {
+        self.children()
+    }
+}
+
+impl MediaExtension for atom_syndication::extension::Extension {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs().get(name).map(String::as_str)
+    }
+
+    fn children(&self) -> &BTreeMap<String, Vec<Self>> {
+        self.children()
+    }
+}
+
+// Media RSS: a media:thumbnail, else an image media:content, looking inside a
+// media:group too (as YouTube does).
+fn media_image<E: MediaExtension>(media: &BTreeMap<String, Vec<E>>) -> Option<String> {
+    let all = |name: &str| media.get(name).into_iter().flatten();
+
+    all("thumbnail")
+        .chain(all("content").filter(|content| {
+            content.attr("medium") == Some("image")
+                || content
+                    .attr("type")
+                    .is_some_and(|mime| mime.starts_with("image/"))
+        }))
+        .find_map(|extension| extension.attr("url").and_then(non_empty))
+        .or_else(|| all("group").find_map(|group| media_image(group.children())))
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>RSS Feed</title>
+    <link>https://example.com/</link>
+    <atom:link href="https://example.com/rss.xml" rel="self" type="application/rss+xml"/>
+    <description>An RSS feed</description>
+    <item>
+      <title>First</title>
+      <link>https://example.com/first</link>
+      <guid>first-guid</guid>
+      <pubDate>Wed, 20 Nov 2024 10:00:00 +0000</pubDate>
+      <description>Hello from RSS</description>
+      <author>jane@example.com (Jane)</author>
+      <category>news</category>
+      <category>rust</category>
+    </item>
+  </channel>
+</rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Atom Feed</title>
+  <id>urn:example:feed</id>
+  <updated>2024-11-20T10:00:00Z</updated>
+  <link rel="self" href="https://example.com/atom.xml"/>
+  <link rel="alternate" href="https://example.com/"/>
+  <entry>
+    <title>Release v1.0</title>
+    <id>tag:example.com,2024:release-1</id>
+    <link rel="alternate" href="https://example.com/releases/1"/>
+    <published>2024-11-20T09:00:00Z</published>
+    <updated>2024-11-20T10:00:00Z</updated>
+    <summary>Hello from Atom</summary>
+    <author><name>Release Bot</name></author>
+    <category term="releases" label="Releases"/>
+  </entry>
+</feed>"#;
+
+    #[test]
+    fn test_read_rss() {
+        let feed = Feed::read_from(RSS.as_bytes()).unwrap();
+
+        assert_eq!(feed.title(), "RSS Feed");
+        assert_eq!(feed.link.as_deref(), Some("https://example.com/"));
+        assert_eq!(
+            feed.self_link.as_deref(),
+            Some("https://example.com/rss.xml")
+        );
+        assert_eq!(feed.items().len(), 1);
+
+        let item = &feed.items()[0];
+        assert_eq!(item.title.as_deref(), Some("First"));
+        assert_eq!(item.link.as_deref(), Some("https://example.com/first"));
+        assert_eq!(item.id.as_deref(), Some("first-guid"));
+        assert_eq!(item.summary.as_deref(), Some("Hello from RSS"));
+        assert_eq!(item.author.as_deref(), Some("jane@example.com (Jane)"));
+        assert_eq!(item.categories, vec!["news", "rust"]);
+        assert_eq!(
+            item.date().unwrap(),
+            DateTime::parse_from_rfc2822("Wed, 20 Nov 2024 10:00:00 +0000").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_atom() {
+        let feed = Feed::read_from(ATOM.as_bytes()).unwrap();
+
+        assert_eq!(feed.title(), "Atom Feed");
+        assert_eq!(feed.link.as_deref(), Some("https://example.com/"));
+        assert_eq!(
+            feed.self_link.as_deref(),
+            Some("https://example.com/atom.xml")
+        );
+        assert_eq!(feed.items().len(), 1);
+
+        let item = &feed.items()[0];
+        assert_eq!(item.title.as_deref(), Some("Release v1.0"));
+        assert_eq!(item.link.as_deref(), Some("https://example.com/releases/1"));
+        assert_eq!(item.id.as_deref(), Some("tag:example.com,2024:release-1"));
+        assert_eq!(item.summary.as_deref(), Some("Hello from Atom"));
+        assert_eq!(item.author.as_deref(), Some("Release Bot"));
+        assert_eq!(item.categories, vec!["Releases"]);
+        assert_eq!(
+            item.date().unwrap(),
+            DateTime::parse_from_rfc3339("2024-11-20T09:00:00Z").unwrap(),
+            "Published date should win over updated"
+        );
+    }
+
+    const JSON_FEED: &str = r#"{
+  "version": "https://jsonfeed.org/version/1.1",
+  "title": "JSON Feed",
+  "home_page_url": "https://example.com/",
+  "feed_url": "https://example.com/feed.json",
+  "items": [
+    {
+      "id": "1",
+      "url": "https://example.com/posts/1",
+      "title": "Hello JSON",
+      "content_text": "Hello from JSON Feed",
+      "date_published": "2024-11-20T10:00:00+00:00",
+      "authors": [{ "name": "Jay" }],
+      "tags": ["json"]
+    },
+    {
+      "id": 2,
+      "external_url": "https://elsewhere.example.com/2",
+      "content_html": "<p>Untitled</p>",
+      "date_modified": "2024-11-21T10:00:00Z"
+    }
+  ]
+}"#;
+
+    #[test]
+    fn test_read_json_feed() {
+        let feed = Feed::read_from(JSON_FEED.as_bytes()).unwrap();
+
+        assert_eq!(feed.title(), "JSON Feed");
+        assert_eq!(feed.link.as_deref(), Some("https://example.com/"));
+        assert_eq!(
+            feed.self_link.as_deref(),
+            Some("https://example.com/feed.json")
+        );
+        assert_eq!(feed.items().len(), 2);
+
+        let first = &feed.items()[0];
+        assert_eq!(first.title.as_deref(), Some("Hello JSON"));
+        assert_eq!(first.link.as_deref(), Some("https://example.com/posts/1"));
+        assert_eq!(first.id.as_deref(), Some("1"));
+        assert_eq!(first.summary.as_deref(), Some("Hello from JSON Feed"));
+        assert_eq!(first.author.as_deref(), Some("Jay"));
+        assert_eq!(first.categories, vec!["json"]);
+        assert_eq!(
+            first.date().unwrap(),
+            DateTime::parse_from_rfc3339("2024-11-20T10:00:00Z").unwrap()
+        );
+
+        let second = &feed.items()[1];
+        assert_eq!(second.id.as_deref(), Some("2"), "Numeric ids are coerced");
+        assert_eq!(
+            second.link.as_deref(),
+            Some("https://elsewhere.example.com/2")
+        );
+        assert!(second.published.is_none());
+        assert!(second.date().is_some(), "Should fall back to date_modified");
+    }
+
+    #[test]
+    fn test_read_icons_and_images() {
+        let rss = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/">
+  <channel>
+    <title>RSS Feed</title>
+    <link>https://example.com/</link>
+    <description>An RSS feed</description>
+    <image>
+      <url>https://example.com/logo.png</url>
+      <title>RSS Feed</title>
+      <link>https://example.com/</link>
+    </image>
+    <item>
+      <title>Enclosure</title>
+      <enclosure url="https://example.com/photo.jpg" length="1" type="image/jpeg"/>
+    </item>
+    <item>
+      <title>Thumbnail</title>
+      <media:thumbnail url="https://example.com/thumb.jpg"/>
+    </item>
+    <item>
+      <title>Podcast</title>
+      <enclosure url="https://example.com/episode.mp3" length="1" type="audio/mpeg"/>
+    </item>
+  </channel>
+</rss>"#;
+        let feed = Feed::read_from(rss.as_bytes()).unwrap();
+        assert_eq!(feed.icon.as_deref(), Some("https://example.com/logo.png"));
+        let images: Vec<_> = feed.items().iter().map(|i| i.image.as_deref()).collect();
+        assert_eq!(
+            images,
+            vec![
+                Some("https://example.com/photo.jpg"),
+                Some("https://example.com/thumb.jpg"),
+                None
+            ]
+        );
+
+        let atom = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">
+  <title>Videos</title>
+  <id>urn:example:videos</id>
+  <updated>2024-11-20T10:00:00Z</updated>
+  <icon>https://example.com/favicon.png</icon>
+  <entry>
+    <title>A video</title>
+    <id>urn:example:video:1</id>
+    <updated>2024-11-20T10:00:00Z</updated>
+    <media:group>
+      <media:title>A video</media:title>
+      <media:thumbnail url="https://example.com/video.jpg" width="480" height="360"/>
+    </media:group>
+  </entry>
+</feed>"#;
+        let feed = Feed::read_from(atom.as_bytes()).unwrap();
+        assert_eq!(
+            feed.icon.as_deref(),
+            Some("https://example.com/favicon.png")
+        );
+        assert_eq!(
+            feed.items()[0].image.as_deref(),
+            Some("https://example.com/video.jpg")
+        );
+
+        let json = r#"{
+  "version": "https://jsonfeed.org/version/1.1",
+  "title": "JSON Feed",
+  "favicon": "https://example.com/favicon.ico",
+  "items": [{ "id": "1", "image": "https://example.com/cover.png" }]
+}"#;
+        let feed = Feed::read_from(json.as_bytes()).unwrap();
+        assert_eq!(
+            feed.icon.as_deref(),
+            Some("https://example.com/favicon.ico")
+        );
+        assert_eq!(
+            feed.items()[0].image.as_deref(),
+            Some("https://example.com/cover.png")
+        );
+        assert!(Feed::read_from(RSS.as_bytes()).unwrap().icon.is_none());
+    }
+
+    #[test]
+    fn test_read_with_content_type() {
+        let padded = format!("\n  {}", JSON_FEED);
+        assert!(Feed::read_with_content_type(padded.as_bytes(), None).is_ok());
+        assert!(
+            Feed::read_with_content_type(JSON_FEED.as_bytes(), Some("application/feed+json"))
+                .is_ok()
+        );
+        assert!(
+            Feed::read_with_content_type(RSS.as_bytes(), Some("application/json")).is_err(),
+            "A JSON content type should not be parsed as XML"
+        );
+    }
+
+    #[test]
+    fn test_read_garbage() {
+        assert!(Feed::read_from(b"<html><body>nope</body></html>").is_err());
+        assert!(Feed::read_from(br#"{"not": "a feed"}"#).is_err());
+    }
+}
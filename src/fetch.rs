@@ -0,0 +1,710 @@
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use reqwest::header::{
+    CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION, RETRY_AFTER,
+};
+use reqwest::{Client, StatusCode, Url, redirect};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::config::FetchConfig;
+use crate::data::{FeedLinkData, HttpCache};
+use crate::discover::{discover_feed_links, is_html};
+use crate::error::{Error, Result};
+use crate::feed::Feed;
+
+// moved_to is set when every redirect on the way was permanent (301/308), so
+// the feed should be subscribed under that address from now on.
+pub enum FetchedFeed {
+    Modified {
+        feed: Feed,
+        cache: HttpCache,
+        moved_to: Option<String>,
+    },
+    NotModified {
+        moved_to: Option<String>,
+    },
+}
+
+impl FetchedFeed {
+    pub fn moved_to(&self) -> Option<&str> {
+        match self {
+            FetchedFeed::Modified { moved_to, .. } => moved_to.as_deref(),
+            FetchedFeed::NotModified { moved_to } => moved_to.as_deref(),
+        }
+    }
+}
+
+// One client (and connection pool) shared by every fetch in a run.
+pub struct Fetcher {
+    client: Client,
+    config: FetchConfig,
+}
+
+impl Fetcher {
+    pub fn new(config: &FetchConfig) -> Result<Self> {
+        let client = Client::builder()
+            .user_agent(&config.user_agent)
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .read_timeout(Duration::from_secs(config.read_timeout_secs))
+            // Followed by hand in fetch, to tell permanent redirects apart.
+            .redirect(redirect::Policy::none())
+            .gzip(true)
+            .brotli(true)
+            .build()
+            .map_err(|e| Error::config("Invalid fetch settings", e))?;
+
+        Ok(Fetcher {
+            client,
+            config: config.clone(),
+        })
+    }
+
+    pub fn config(&self) -> &FetchConfig {
+        &self.config
+    }
+
+    // Retries transient failures with jittered exponential backoff, or for as
+    // long as a Retry-After asks if that's within max_retry_wait_secs.
+    pub async fn fetch_with_retry(&self, link: &str, cache: &HttpCache) -> Result<FetchedFeed> {
+        let max_wait = Duration::from_secs(self.config.max_retry_wait_secs);
+        let mut attempt = 0;
+
+        loop {
+            let error = match self.fetch(link, cache).await {
+                Ok(fetched) => return Ok(fetched),
+                Err(e) => e,
+            };
+            if attempt >= self.config.max_retries || !error.is_transient() {
+                return Err(error);
+            }
+
+            let delay = match error.retry_after() {
+                Some(wait) if wait > max_wait => return Err(error),
+                Some(wait) => wait,
+                None => jittered_backoff(
+                    Duration::from_millis(self.config.retry_base_delay_ms),
+                    attempt,
+                ),
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    // Sends the validators from the previous fetch, so an unchanged feed costs a
+    // 304 instead of a download and parse.
+    pub async fn fetch(&self, link: &str, cache: &HttpCache) -> Result<FetchedFeed> {
+        self.fetch_or_discover(link, cache, true).await
+    }
+
+    // A web page instead of a feed is followed (once) to the first feed it
+    // advertises, which is reported as where the feed moved to.
+    async fn fetch_or_discover(
+        &self,
+        link: &str,
+        cache: &HttpCache,
+        discover: bool,
+    ) -> Result<FetchedFeed> {
+        let mut url = Url::parse(link).map_err(|e| Error::Fetch {
+            url: link.to_string(),
+            reason: "invalid link".to_string(),
+            source: Some(Box::new(e)),
+        })?;
+        let mut moved_to = None;
+        let mut all_permanent = true;
+        let mut redirects = 0;
+
+        let response = loop {
+            let mut request = self.client.get(url.clone());
+            if let Some(etag) = &cache.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cache.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+
+            let response = request
+                .send()
+                .await
+                .map_err(|e| Error::request(url.as_str(), e))?;
+            let status = response.status();
+            if !status.is_redirection() || status == StatusCode::NOT_MODIFIED {
+                break response;
+            }
+
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| {
+                    Error::fetch(
+                        url.as_str(),
+                        format!("{} redirect without a Location", status),
+                    )
+                })?;
+            if redirects >= self.config.max_redirects {
+                return Err(Error::fetch(link, "too many redirects"));
+            }
+            redirects += 1;
+            url = url.join(location).map_err(|e| Error::Fetch {
+                url: url.to_string(),
+                reason: format!("invalid redirect to {}", location),
+                source: Some(Box::new(e)),
+            })?;
+
+            all_permanent &= matches!(
+                status,
+                StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+            );
+            if all_permanent {
+                moved_to = Some(url.to_string());
+            }
+        };
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(FetchedFeed::NotModified { moved_to });
+        }
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+            return Err(Error::HttpStatus {
+                url: url.to_string(),
+                status,
+                retry_after,
+            });
+        }
+        let mut response = response;
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let content_type = header(CONTENT_TYPE);
+        let cache = HttpCache {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+
+        // Content-Length can't be trusted (or may be missing), so count as we go.
+        let mut content = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| Error::request(url.as_str(), e))?
+        {
+            content.extend_from_slice(&chunk);
+            if content.len() as u64 > self.config.max_body_bytes {
+                return Err(Error::fetch(
+                    url.as_str(),
+                    format!("larger than the {} byte limit", self.config.max_body_bytes),
+                ));
+            }
+        }
+
+        let feed = match Feed::read_with_content_type(&content, content_type.as_deref()) {
+            Ok(feed) => feed,
+            Err(e) if !discover || !is_html(&content, content_type.as_deref()) => return Err(e),
+            Err(_) => {
+                let links = discover_feed_links(&String::from_utf8_lossy(&content), &url);
+                let Some(found) = links.into_iter().next() else {
+                    return Err(Error::fetch(link, "web page without a feed"));
+                };
+                let fetched =
+                    Box::pin(self.fetch_or_discover(&found, &HttpCache::default(), false)).await?;
+                return Ok(match fetched {
+                    FetchedFeed::Modified {
+                        feed,
+                        cache,
+                        moved_to,
+                    } => FetchedFeed::Modified {
+                        feed,
+                        cache,
+                        moved_to: moved_to.or(Some(found)),
+                    },
+                    FetchedFeed::NotModified { moved_to } => FetchedFeed::NotModified {
+                        moved_to: moved_to.or(Some(found)),
+                    },
+                });
+            }
+        };
+        Ok(FetchedFeed::Modified {
+            feed,
+            cache,
+            moved_to,
+        })
+    }
+
+    // Fetches every feed at once, within the configured overall and per-host
+    // limits. Results come back in the same order as the feeds. A feed with
+    // snoozed items due again is fetched in full, as a 304 would hide them.
+    pub async fn fetch_all(&self, feeds: &[FeedLinkData]) -> Vec<Result<FetchedFeed>> {
+        let hosts: Vec<String> = feeds.iter().map(|feed| host_of(feed.feed_link())).collect();
+
+        let all_hosts = Semaphore::new(self.config.max_concurrent.max(1));
+        let mut per_host: HashMap<&str, Semaphore> = HashMap::new();
+        for host in &hosts {
+            per_host
+                .entry(host)
+                .or_insert_with(|| Semaphore::new(self.config.max_concurrent_per_host.max(1)));
+        }
+
+        let unconditional = HttpCache::default();
+        let fetches = feeds.iter().zip(&hosts).map(|(feed, host)| {
+            let host_limit = &per_host[host.as_str()];
+            let all_hosts = &all_hosts;
+            let cache = if feed.has_snoozes_over() {
+                &unconditional
+            } else {
+                feed.http_cache()
+            };
+            async move {
+                // Wait on the host first so a busy host doesn't hold up an overall slot.
+                let _host_permit = host_limit.acquire().await;
+                let _permit = all_hosts.acquire().await;
+                self.fetch_with_retry(feed.feed_link(), cache).await
+            }
+        });
+
+        join_all(fetches).await
+    }
+}
+
+// Somewhere between half and all of base * 2^attempt, so feeds that failed
+// together don't all retry at the same moment.
+pub(crate) fn jittered_backoff(base: Duration, attempt: u32) -> Duration {
+    let delay = base.saturating_mul(2u32.saturating_pow(attempt));
+    let half = delay / 2;
+    half + half.mul_f64(fastrand::f64())
+}
+
+// Retry-After is either a number of seconds or an HTTP date.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+// Links that don't parse are only limited against themselves.
+fn host_of(link: &str) -> String {
+    Url::parse(link)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| link.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn rss_with_title(title: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?><rss version="2.0"><channel><title>{}</title><link>https://example.com/</link><description>test</description></channel></rss>"#,
+            title
+        )
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_limits_per_host_and_keeps_order() {
+        let server = MockServer::start().await;
+        let delay = Duration::from_millis(200);
+
+        for name in ["a", "b", "c"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", name)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(rss_with_title(name))
+                        .set_delay(delay),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let feeds: Vec<FeedLinkData> = ["c", "a", "b"]
+            .iter()
+            .map(|name| FeedLinkData::new_for_testing(&format!("{}/{}", server.uri(), name), ""))
+            .collect();
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            max_concurrent: 8,
+            max_concurrent_per_host: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let started = Instant::now();
+        let results = fetcher.fetch_all(&feeds).await;
+
+        assert!(
+            started.elapsed() >= delay * 3,
+            "One at a time for a single host"
+        );
+        let titles: Vec<String> = results
+            .into_iter()
+            .map(|r| match r.unwrap() {
+                FetchedFeed::Modified { feed, .. } => feed.title().to_string(),
+                FetchedFeed::NotModified { .. } => panic!("Nothing was cached"),
+            })
+            .collect();
+        assert_eq!(titles, vec!["c", "a", "b"]);
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            max_concurrent: 8,
+            max_concurrent_per_host: 3,
+            ..Default::default()
+        })
+        .unwrap();
+        let started = Instant::now();
+        let results = fetcher.fetch_all(&feeds).await;
+
+        assert!(started.elapsed() < delay * 3, "All at once");
+        assert!(results.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_if_modified() {
+        let server = MockServer::start().await;
+        let etag = "\"v1\"";
+        let last_modified = "Wed, 20 Nov 2024 10:00:00 GMT";
+
+        Mock::given(method("GET"))
+            .and(header("If-None-Match", etag))
+            .respond_with(ResponseTemplate::new(304))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", etag)
+                    .insert_header("Last-Modified", last_modified)
+                    .set_body_string(rss_with_title("cached")),
+            )
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let first = fetcher
+            .fetch(&server.uri(), &HttpCache::default())
+            .await
+            .unwrap();
+        let FetchedFeed::Modified { feed, cache, .. } = first else {
+            panic!("First fetch has nothing to validate against");
+        };
+        assert_eq!(feed.title(), "cached");
+        assert_eq!(cache.etag.as_deref(), Some(etag));
+        assert_eq!(cache.last_modified.as_deref(), Some(last_modified));
+
+        let second = fetcher.fetch(&server.uri(), &cache).await.unwrap();
+        assert!(matches!(second, FetchedFeed::NotModified { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_retries_server_errors() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rss_with_title("retried")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let result = fetcher
+            .fetch_with_retry(&server.uri(), &HttpCache::default())
+            .await;
+        assert!(matches!(result, Ok(FetchedFeed::Modified { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let error = fetcher
+            .fetch_with_retry(&server.uri(), &HttpCache::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(!error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_gives_up_on_long_retry_after() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+
+        let error = fetcher
+            .fetch_with_retry(&server.uri(), &HttpCache::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.is_transient());
+        assert_eq!(
+            error.retry_after(),
+            Some(Duration::from_secs(3600)),
+            "Retry-After should be kept for the persistent backoff"
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO),
+            "Dates in the past mean go ahead"
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_jittered_backoff() {
+        let base = Duration::from_millis(100);
+        for attempt in 0..4 {
+            let delay = jittered_backoff(base, attempt);
+            let full = base * 2u32.pow(attempt);
+            assert!(delay >= full / 2 && delay <= full);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_sends_user_agent() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(header("User-Agent", "test-agent/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rss_with_title("ua")))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            user_agent: String::from("test-agent/1.0"),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(
+            fetcher
+                .fetch(&server.uri(), &HttpCache::default())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_limits_body_size() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rss_with_title("big")))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            max_body_bytes: 16,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let result = fetcher.fetch(&server.uri(), &HttpCache::default()).await;
+        assert!(result.is_err(), "Body is over the limit");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_follows_permanent_redirects() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/old"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", "/middle"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/middle"))
+            .respond_with(ResponseTemplate::new(308).insert_header("Location", "/new"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/new"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rss_with_title("moved")))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let fetched = fetcher
+            .fetch(&format!("{}/old", server.uri()), &HttpCache::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fetched.moved_to(),
+            Some(format!("{}/new", server.uri()).as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_ignores_temporary_redirects() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/old"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", "/middle"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/middle"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/temporary"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/temporary"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rss_with_title("moved")))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let fetched = fetcher
+            .fetch(&format!("{}/old", server.uri()), &HttpCache::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fetched.moved_to(),
+            Some(format!("{}/middle", server.uri()).as_str()),
+            "Only the permanent part of the chain should stick"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_limits_redirects() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/loop"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("Location", format!("{}/loop", server.uri())),
+            )
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            max_redirects: 2,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let result = fetcher
+            .fetch(&format!("{}/loop", server.uri()), &HttpCache::default())
+            .await;
+        assert!(result.is_err(), "Should give up after too many redirects");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_discovers_feed_from_web_page() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/news/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "text/html")
+                    .set_body_string(
+                        r#"<html><head><link rel="alternate" type="application/rss+xml" href="/feeds/news/"></head></html>"#,
+                    ),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/feeds/news/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rss_with_title("news")))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let fetched = fetcher
+            .fetch(&format!("{}/news/", server.uri()), &HttpCache::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fetched.moved_to(),
+            Some(format!("{}/feeds/news/", server.uri()).as_str())
+        );
+        match fetched {
+            FetchedFeed::Modified { feed, .. } => assert_eq!(feed.title(), "news"),
+            FetchedFeed::NotModified { .. } => panic!("Expected the discovered feed"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_web_page_without_feed() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("<!DOCTYPE html><html><head></head></html>"),
+            )
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let error = fetcher
+            .fetch(&server.uri(), &HttpCache::default())
+            .await
+            .err()
+            .unwrap();
+
+        assert!(error.to_string().contains("web page without a feed"));
+    }
+}
@@ -0,0 +1,285 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::FetchConfig;
+use crate::data::get_data_dir;
+use crate::error::{Error, Result};
+
+// Fetched again after this long, in case a feed changes its icon.
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// A download that failed isn't tried again for this long, so a site without a
+// favicon doesn't hold up every popup.
+const RETRY_FAILED_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+// Files nothing has asked for in this long are removed.
+const PRUNE_AFTER: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// Anything bigger isn't worth showing in a notification.
+const MAX_SIZE: usize = 2 * 1024 * 1024;
+
+// Feed icons and item images, downloaded once into the data directory so
+// notifications can be given a local file.
+pub struct ImageCache {
+    client: Client,
+    dir: PathBuf,
+}
+
+impl ImageCache {
+    pub fn new(data_path: Option<&str>, config: &FetchConfig) -> Result<Self> {
+        let dir = get_data_dir(data_path)?.join("images");
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        prune(&dir);
+
+        let client = Client::builder()
+            .user_agent(&config.user_agent)
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.read_timeout_secs))
+            .build()
+            .map_err(|e| Error::config("Invalid image download settings", e))?;
+
+        Ok(ImageCache { client, dir })
+    }
+
+    // The cached file for url, downloading it first if it's missing or stale.
+    pub async fn get(&self, url: &str) -> Result<PathBuf> {
+        let parsed = Url::parse(url).map_err(|e| Error::Fetch {
+            url: url.to_string(),
+            reason: String::from("invalid image link"),
+            source: Some(Box::new(e)),
+        })?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(Error::fetch(
+                url,
+                "only http and https images are supported",
+            ));
+        }
+
+        let path = self.dir.join(cache_name(&parsed));
+        if is_younger_than(&path, MAX_AGE) {
+            return Ok(path);
+        }
+        let failed = path.with_extension("failed");
+        if is_younger_than(&failed, RETRY_FAILED_AFTER) {
+            return Err(Error::fetch(url, "failed recently, not retrying yet"));
+        }
+
+        // The marker is only a hint, so not being able to write it doesn't matter.
+        match self.download(&parsed, &path).await {
+            Ok(()) => {
+                let _ = fs::remove_file(&failed);
+                Ok(path)
+            }
+            Err(e) => {
+                let _ = fs::write(&failed, b"");
+                Err(e)
+            }
+        }
+    }
+
+    async fn download(&self, url: &Url, path: &Path) -> Result<()> {
+        let response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(|e| Error::request(url.as_str(), e))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::HttpStatus {
+                url: url.to_string(),
+                status,
+                retry_after: None,
+            });
+        }
+        // Sites often answer a missing favicon with their HTML home page.
+        let is_image = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_none_or(|mime| mime.starts_with("image/"));
+        if !is_image {
+            return Err(Error::fetch(url.as_str(), "not an image"));
+        }
+
+        // Counted as it arrives, like feeds, since Content-Length may lie.
+        let mut response = response;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| Error::request(url.as_str(), e))?
+        {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > MAX_SIZE {
+                return Err(Error::fetch(url.as_str(), "image is too large"));
+            }
+        }
+
+        // Written aside and renamed, so a popup never gets half a file.
+        let partial = path.with_extension("part");
+        fs::write(&partial, &bytes).map_err(|e| Error::io(&partial, e))?;
+        fs::rename(&partial, path).map_err(|e| Error::io(path, e))
+    }
+}
+
+fn is_younger_than(path: &Path, age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed < age))
+}
+
+// Feeds come and go, and so do their images; the cache shouldn't only grow.
+// A file that can't be removed will be tried again next time.
+fn prune(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !is_younger_than(&path, PRUNE_AFTER) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Where a site keeps its favicon by convention, for feeds without an icon.
+pub fn favicon_url(site: &str) -> Option<String> {
+    let site = Url::parse(site).ok()?;
+    if !matches!(site.scheme(), "http" | "https") {
+        return None;
+    }
+    site.join("/favicon.ico").ok().map(String::from)
+}
+
+// A hash of the url, keeping its extension so image viewers know the format.
+// FNV-1a, since std's hasher may change between releases and orphan the cache.
+fn cache_name(url: &Url) -> String {
+    let hash = url
+        .as_str()
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+    let hash = format!("{:016x}", hash);
+
+    let extension = url
+        .path()
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .filter(|extension| {
+            (1..=4).contains(&extension.len())
+                && extension.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    match extension {
+        Some(extension) => format!("{}.{}", hash, extension),
+        None => hash,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_image_cache_downloads_once() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/icon.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "image/png")
+                    .set_body_bytes(b"not really a png".to_vec()),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/favicon.ico"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "text/html")
+                    .set_body_string("<html></html>"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/huge.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "image/png")
+                    .set_body_bytes(vec![0; MAX_SIZE + 1]),
+            )
+            .mount(&server)
+            .await;
+
+        let test_path = "./test-image-cache";
+        let cache = ImageCache::new(Some(test_path), &FetchConfig::default()).unwrap();
+        let url = format!("{}/icon.png", server.uri());
+
+        let first = cache.get(&url).await.unwrap();
+        let second = cache.get(&url).await.unwrap();
+        assert_eq!(first, second, "Served from the cache the second time");
+        assert!(first.starts_with("./test-image-cache/images"));
+        assert_eq!(first.extension().unwrap(), "png");
+        assert_eq!(std::fs::read(&first).unwrap(), b"not really a png");
+
+        let favicon = favicon_url(&format!("{}/blog/", server.uri())).unwrap();
+        assert_eq!(favicon, format!("{}/favicon.ico", server.uri()));
+        assert!(cache.get(&favicon).await.is_err(), "HTML isn't an image");
+        assert!(
+            cache.get(&favicon).await.is_err(),
+            "Not asked for again so soon"
+        );
+        assert!(cache.get("file:///etc/passwd").await.is_err());
+
+        let huge = cache
+            .get(&format!("{}/huge.png", server.uri()))
+            .await
+            .unwrap_err();
+        assert!(huge.report().contains("too large"), "{}", huge.report());
+
+        std::fs::remove_dir_all(test_path).ok();
+    }
+
+    #[test]
+    fn test_image_cache_prunes_old_files() {
+        let test_path = "./test-image-cache-prune";
+        let dir = Path::new(test_path).join("images");
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("old.png");
+        let recent = dir.join("recent.png");
+        fs::write(&recent, b"").unwrap();
+        fs::File::create(&old)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - PRUNE_AFTER)
+            .unwrap();
+
+        ImageCache::new(Some(test_path), &FetchConfig::default()).unwrap();
+        assert!(!old.exists());
+        assert!(recent.exists());
+
+        fs::remove_dir_all(test_path).ok();
+    }
+
+    #[test]
+    fn test_cache_name() {
+        let name = |url: &str| cache_name(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            name("https://example.com/a.png"),
+            "fa378f653a3984b6.png",
+            "Stable between builds"
+        );
+        assert!(name("https://example.com/a.JPG").ends_with(".jpg"));
+        assert!(!name("https://example.com/image?id=1").contains('.'));
+        assert!(!name("https://example.com/v1.2/image").contains('.'));
+        assert_ne!(
+            name("https://example.com/a.png"),
+            name("https://example.com/b.png")
+        );
+    }
+}
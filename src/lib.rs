@@ -1,51 +1,83 @@
-use chrono::DateTime;
-use notify_rust::Notification;
-use rss::{Channel, Item};
-use std::{collections::HashSet, error::Error};
-
-use crate::data::FeedLinkData;
+use chrono::{DateTime, FixedOffset};
+use reqwest::Url;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::config::Templates;
+use crate::data::{FeedLinkData, HttpCache};
+use crate::feed::{Feed, FeedItem};
+use crate::fetch::{FetchedFeed, Fetcher};
+use crate::images::favicon_url;
+use crate::notifier::{NotificationData, Notifier};
 
 pub mod config;
 pub mod data;
-
-pub async fn get_feed(link: &str) -> Result<Channel, Box<dyn Error>> {
-    let content = reqwest::get(link).await?.bytes().await?;
-    let channel = Channel::read_from(&content[..])?;
-    Ok(channel)
+pub mod discover;
+pub mod error;
+pub mod feed;
+pub mod fetch;
+pub mod images;
+pub mod notifier;
+
+pub use crate::error::{Error, Result};
+
+// Reads a single feed through a shared fetcher; None if it hasn't changed
+// since cache was filled.
+pub async fn get_feed(fetcher: &Fetcher, link: &str, cache: &HttpCache) -> Result<Option<Feed>> {
+    match fetcher.fetch(link, cache).await? {
+        FetchedFeed::Modified { feed, .. } => Ok(Some(feed)),
+        FetchedFeed::NotModified { .. } => Ok(None),
+    }
 }
 
-pub fn is_item_unseen(pub_date: &str, last_seen: &str) -> Result<bool, chrono::ParseError> {
-    let pub_date = pub_date.trim();
-    let last_seen = last_seen.trim();
-    let formatted_pub_date = DateTime::parse_from_rfc2822(pub_date)?;
-    let formatted_last_seen = DateTime::parse_from_rfc2822(last_seen)?;
+// Undated items can't be compared, so they are never considered unseen here.
+pub fn is_item_unseen(item: &FeedItem, last_seen: &str) -> Result<bool> {
+    let Some(pub_date) = item.date() else {
+        return Ok(false);
+    };
+    let formatted_last_seen = DateTime::parse_from_rfc2822(last_seen.trim())
+        .map_err(|e| Error::config(format!("Invalid last_seen date \"{}\"", last_seen), e))?;
 
-    Ok(formatted_pub_date > formatted_last_seen)
+    Ok(pub_date > formatted_last_seen)
 }
 
-pub async fn check_items(items: &[Item], last_seen: &str) -> Result<Vec<Item>, Box<dyn Error>> {
-    let mut unseen_items: Vec<Item> = vec![];
+// Items are matched by guid/link against the ones remembered for the feed. Until
+// a feed has any remembered items (or for items with neither), dates are used.
+// Seen items whose snooze is over count as unseen again.
+pub async fn check_items(items: &[FeedItem], feed: &FeedLinkData) -> Result<Vec<FeedItem>> {
+    let mut unseen_items: Vec<FeedItem> = vec![];
+    let last_seen = feed.last_seen();
 
     for item in items {
-        let pub_date = item.pub_date();
-        match pub_date {
-            Some(date) => {
-                let is_unseen = is_item_unseen(date, last_seen);
-                match is_unseen {
-                    Ok(unseen) => {
-                        if unseen {
-                            unseen_items.push(item.clone());
-                        } else {
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        println!("Someting went wrong: {}", e);
-                    }
-                }
+        if let Some(id) = item.identity()
+            && feed.is_snooze_over(id)
+        {
+            unseen_items.push(item.clone());
+            continue;
+        }
+
+        if feed.has_seen_items()
+            && let Some(id) = item.identity()
+        {
+            if !feed.is_item_seen(id) {
+                unseen_items.push(item.clone());
             }
-            None => {
-                println!("Warning!: Item {} has no date.", item.link().unwrap());
+            continue;
+        }
+
+        if item.date().is_none() {
+            eprintln!(
+                "Warning!: Item {} has no date.",
+                item.link.as_deref().unwrap_or("<no link>")
+            );
+            continue;
+        }
+
+        match is_item_unseen(item, last_seen) {
+            Ok(true) => unseen_items.push(item.clone()),
+            Ok(false) => (),
+            Err(e) => {
+                eprintln!("Someting went wrong: {}", e);
             }
         }
     }
@@ -53,100 +85,232 @@ pub async fn check_items(items: &[Item], last_seen: &str) -> Result<Vec<Item>, B
     Ok(unseen_items)
 }
 
-pub struct NotificationData {
-    title: String,
-    unseen_items_count: u64,
-    latest_item: Item,
+// Feeds aren't reliably sorted, so go by date. Undated items and ties keep
+// whichever was listed first.
+pub fn newest_item(items: &[FeedItem]) -> Option<&FeedItem> {
+    items.iter().reduce(|newest, item| {
+        if item.date() > newest.date() {
+            item
+        } else {
+            newest
+        }
+    })
 }
 
-impl NotificationData {
-    fn create_body(&self) -> String {
-        let item_title = self.latest_item.title().unwrap_or("Untitled");
+// A feed that was fetched successfully, with every item identity it listed so
+// they can be remembered as seen, and the newest item date to advance last_seen.
+// http_cache is None when the feed was not modified and the old one still holds.
+// moved_to is the feed's new address, from a permanent redirect or a changed
+// self link. self_link is the one the feed gave this time, if it was read.
+// unsnoozed_ids are snoozes that are over: items that were due again and
+// notified, and items that left the feed before they were due.
+pub struct CheckedFeed<'a> {
+    pub feed_link: &'a str,
+    pub item_ids: Vec<String>,
+    pub unsnoozed_ids: Vec<String>,
+    pub newest_item_date: Option<DateTime<FixedOffset>>,
+    pub unseen_items_count: usize,
+    pub http_cache: Option<HttpCache>,
+    pub moved_to: Option<String>,
+    pub self_link: Option<String>,
+}
 
-        format!("Latest Item: <i>{}</i>\nClick to read more!", item_title)
-    }
-    fn create_subject(&self) -> String {
-        format!("{}, {} unread items!", self.title, self.unseen_items_count)
-    }
-    pub fn send_notify(&self) -> Result<(), Box<dyn Error>> {
-        let subject = self.create_subject();
-        let body = self.create_body();
-        let link = self.latest_item.link().unwrap_or("");
+// A feed that couldn't be fetched, even after retrying, so it can be backed off.
+pub struct FailedFeed<'a> {
+    pub feed_link: &'a str,
+    pub error: String,
+    pub retry_after: Option<Duration>,
+}
 
-        let mut notification = Notification::new();
-        notification
-            .summary(&subject)
-            .body(&body)
-            .action("default", "Open");
+pub struct CheckResults<'a> {
+    pub checked: Vec<CheckedFeed<'a>>,
+    pub failed: Vec<FailedFeed<'a>>,
+}
 
-        let handle = notification.show()?;
+// The feed's own idea of where it lives, when that's a usable address other
+// than the one it was fetched from.
+pub fn moved_by_self_link(feed: &Feed, link: &str) -> Option<String> {
+    let self_link = Url::parse(feed.self_link.as_deref()?).ok()?;
+    if !matches!(self_link.scheme(), "http" | "https") {
+        return None;
+    }
+    if Url::parse(link).is_ok_and(|link| link == self_link) {
+        return None;
+    }
+    Some(self_link.to_string())
+}
 
-        handle.wait_for_action(|action| match action {
-            "default" => {
-                if !link.is_empty() {
-                    if let Err(e) = open::that(link) {
-                        eprintln!("Failed to open link: {}", e);
-                    }
-                }
-            }
-            "__closed" => (),
-            _ => (),
-        });
+// Stale self links (localhost, an old feedburner address, http for an https
+// feed) are common, so one is only followed when it changes from what the
+// feed said last time, and only to an address that serves a feed itself
+// without redirecting back.
+async fn moved_by_changed_self_link(
+    fetcher: &Fetcher,
+    feed: &FeedLinkData,
+    channel: &Feed,
+    link: &str,
+) -> Option<String> {
+    let previous = feed.self_link()?;
+    if channel.self_link.as_deref() == Some(previous) {
+        return None;
+    }
+    let candidate = moved_by_self_link(channel, link)?;
+
+    let fetched = match fetcher.fetch(&candidate, &HttpCache::default()).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            eprintln!(
+                "Not following {} to its new self link: {}",
+                link,
+                e.report()
+            );
+            return None;
+        }
+    };
+    let destination = fetched.moved_to().unwrap_or(&candidate);
+    if same_url(destination, link) {
+        return None;
+    }
+    Some(destination.to_string())
+}
 
-        Ok(())
+fn same_url(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
     }
 }
 
-pub async fn check_all_feeds_and_notify(
-    feeds: &[FeedLinkData],
-) -> Result<Vec<&str>, Box<dyn Error>> {
+// templates gives the notification templates for a feed link.
+pub async fn check_all_feeds_and_notify<'a>(
+    feeds: &'a [FeedLinkData],
+    fetcher: &Fetcher,
+    notifiers: &[Box<dyn Notifier>],
+    templates: impl Fn(&FeedLinkData) -> Templates,
+) -> Result<CheckResults<'a>> {
     let mut notifications: Vec<NotificationData> = Vec::new();
-    let mut unseen_feeds: Vec<&str> = Vec::new();
+    let mut checked_feeds: Vec<CheckedFeed> = Vec::new();
+    let mut failed_feeds: Vec<FailedFeed> = Vec::new();
+
+    let fetched = fetcher.fetch_all(feeds).await;
 
-    for feed in feeds {
+    for (feed, result) in feeds.iter().zip(fetched) {
         let feed_link = feed.feed_link();
-        let channel = match get_feed(feed_link).await {
-            Ok(c) => c,
+        let (channel, http_cache, moved_to) = match result {
+            Ok(FetchedFeed::Modified {
+                feed: c,
+                cache,
+                moved_to,
+            }) => (c, cache, moved_to),
+            Ok(FetchedFeed::NotModified { moved_to }) => {
+                checked_feeds.push(CheckedFeed {
+                    feed_link,
+                    item_ids: Vec::new(),
+                    unsnoozed_ids: Vec::new(),
+                    newest_item_date: None,
+                    unseen_items_count: 0,
+                    http_cache: None,
+                    moved_to,
+                    self_link: None,
+                });
+                continue;
+            }
             Err(e) => {
-                eprintln!("Failed to fetch feed {}: {}", feed_link, e);
+                eprintln!("Failed to check feed {}: {}", feed_link, e.report());
+                failed_feeds.push(FailedFeed {
+                    feed_link,
+                    error: e.report(),
+                    retry_after: e.retry_after(),
+                });
                 continue;
             }
         };
 
         let items = channel.items();
-        if items.is_empty() {
-            continue;
-        }
+        let mut unseen = check_items(items, feed).await?;
+        let moved_to = match moved_to {
+            Some(moved_to) => Some(moved_to),
+            None => moved_by_changed_self_link(fetcher, feed, &channel, feed_link).await,
+        };
 
-        let last_seen = feed.last_seen();
-        let unseen = check_items(items, last_seen).await?;
+        let item_ids: Vec<String> = items
+            .iter()
+            .filter_map(|item| item.identity().map(String::from))
+            .collect();
+        // A snoozed item gone from the feed never comes back, and while its
+        // snooze is due every check would skip the HTTP cache.
+        let gone_ids = feed
+            .snoozes_over()
+            .filter(|id| !item_ids.iter().any(|item_id| item_id == id));
+        let unsnoozed_ids = unseen
+            .iter()
+            .filter_map(FeedItem::identity)
+            .filter(|id| feed.is_snoozed(id))
+            .chain(gone_ids)
+            .map(String::from)
+            .collect();
+
+        checked_feeds.push(CheckedFeed {
+            feed_link,
+            item_ids,
+            unsnoozed_ids,
+            newest_item_date: items.iter().filter_map(FeedItem::date).max(),
+            unseen_items_count: unseen.len(),
+            http_cache: Some(http_cache),
+            moved_to,
+            self_link: channel.self_link.clone(),
+        });
 
-        if unseen.is_empty() {
+        if unseen.is_empty() || feed.is_muted() {
             continue;
         }
 
-        unseen_feeds.push(feed.feed_link());
-        let latest_item = unseen.last().unwrap().clone();
+        // Newest first; undated items go last.
+        unseen.sort_by_key(|item| std::cmp::Reverse(item.date()));
 
         notifications.push(NotificationData {
             title: channel.title().to_string(),
-            unseen_items_count: unseen.len() as u64,
-            latest_item,
+            feed_link: feed_link.to_string(),
+            feed_icon: channel
+                .icon
+                .clone()
+                .or_else(|| favicon_url(channel.link.as_deref().unwrap_or(feed_link))),
+            items: unseen,
+            templates: templates(feed),
         });
     }
 
-    for notify in notifications {
-        notify.send_notify()?;
+    for notification in &notifications {
+        for notifier in notifiers {
+            if let Err(e) = notifier.notify(notification).await {
+                eprintln!(
+                    "Failed to notify about {} through {}: {}",
+                    notification.feed_link,
+                    notifier.name(),
+                    e.report()
+                );
+            }
+        }
     }
 
-    Ok(unseen_feeds)
+    for notifier in notifiers {
+        if let Err(e) = notifier.flush().await {
+            eprintln!(
+                "Failed to notify through {}: {}",
+                notifier.name(),
+                e.report()
+            );
+        }
+    }
+
+    Ok(CheckResults {
+        checked: checked_feeds,
+        failed: failed_feeds,
+    })
 }
 
-pub fn initiate_data_from_config(
-    config: &config::Config,
-    data_path: Option<&str>,
-) -> Result<(), Box<dyn Error>> {
-    let mut data: data::Data = data::Data::load(data_path).expect("Failed to load or create data");
+pub fn initiate_data_from_config(config: &config::Config, data_path: Option<&str>) -> Result<()> {
+    let mut data: data::Data = data::Data::load(data_path)?;
 
     let mut present_feeds: HashSet<String> = data.get_feeds().into_iter().collect();
 
@@ -168,13 +332,17 @@ pub fn initiate_data_from_config(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::FetchConfig;
     use rss::ItemBuilder;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_is_item_unseen_true() {
-        let item = ItemBuilder::default()
-            .pub_date(String::from("Wed, 20 Nov 2024 10:00:00 +0000"))
-            .build();
+        let item = FeedItem::from(
+            &ItemBuilder::default()
+                .pub_date(String::from("Wed, 20 Nov 2024 10:00:00 +0000"))
+                .build(),
+        );
 
         let last_seen = "Wed, 20 Nov 2024 09:00:00 +0000";
 
@@ -186,9 +354,11 @@ mod tests {
 
     #[test]
     fn test_is_item_unseen_false() {
-        let item = ItemBuilder::default()
-            .pub_date(String::from("Wed, 20 Nov 2024 08:00:00 +0000"))
-            .build();
+        let item = FeedItem::from(
+            &ItemBuilder::default()
+                .pub_date(String::from("Wed, 20 Nov 2024 08:00:00 +0000"))
+                .build(),
+        );
 
         let last_seen = "Wed, 20 Nov 2024 09:00:00 +0000";
 
@@ -203,52 +373,168 @@ mod tests {
         let path = String::from("./test-check-items");
 
         let items = vec![
-            ItemBuilder::default()
-                .pub_date(String::from("Wed, 20 Nov 2024 08:00:00 +0000"))
-                .build(),
-            ItemBuilder::default()
-                .pub_date(String::from("Wed, 20 Nov 2024 09:00:00 +0000"))
-                .build(),
-            ItemBuilder::default()
-                .pub_date(String::from("Wed, 20 Nov 2024 10:00:00 +0000"))
-                .build(),
+            FeedItem::from(
+                &ItemBuilder::default()
+                    .pub_date(String::from("Wed, 20 Nov 2024 08:00:00 +0000"))
+                    .build(),
+            ),
+            FeedItem::from(
+                &ItemBuilder::default()
+                    .pub_date(String::from("Wed, 20 Nov 2024 09:00:00 +0000"))
+                    .build(),
+            ),
+            FeedItem::from(
+                &ItemBuilder::default()
+                    .pub_date(String::from("Wed, 20 Nov 2024 10:00:00 +0000"))
+                    .build(),
+            ),
         ];
 
-        let last_seen = "Wed, 20 Nov 2024 08:30:00 +0000";
-        let unseen = check_items(&items, last_seen).await.unwrap();
+        let feed = FeedLinkData::new_for_testing("mock://test", "Wed, 20 Nov 2024 08:30:00 +0000");
+        let unseen = check_items(&items, &feed).await.unwrap();
 
         assert_eq!(unseen.len(), 2, "Should return unseen items");
         assert_eq!(
-            unseen.first().unwrap().pub_date().unwrap(),
-            "Wed, 20 Nov 2024 09:00:00 +0000",
+            unseen.first().unwrap().date().unwrap(),
+            DateTime::parse_from_rfc2822("Wed, 20 Nov 2024 09:00:00 +0000").unwrap(),
             "First unseen should be 9:00 after reverse"
         );
 
         std::fs::remove_dir_all(path).ok();
     }
 
-    #[test]
-    fn test_notification_create_strings() {
-        let item = ItemBuilder::default()
-            .title(String::from("Hello World"))
-            .link(String::from("https://example.com"))
-            .pub_date(String::from("Wed, 20 Nov 2024 11:00:00 +0000"))
-            .build();
-
-        let notif = NotificationData {
-            title: String::from("My Feed"),
-            unseen_items_count: 5,
-            latest_item: item,
+    #[tokio::test]
+    async fn test_check_items_by_guid() {
+        let item = |guid: &str, date: &str| {
+            FeedItem::from(
+                &ItemBuilder::default()
+                    .guid(rss::Guid {
+                        value: String::from(guid),
+                        permalink: false,
+                    })
+                    .pub_date(String::from(date))
+                    .build(),
+            )
         };
 
-        let subject = notif.create_subject();
-        let body = notif.create_body();
+        let mut feed =
+            FeedLinkData::new_for_testing("mock://test", "Wed, 20 Nov 2024 12:00:00 +0000");
+        feed.mark_items_seen(&[String::from("old")]);
+
+        let items = vec![
+            // Backdated, so a date comparison would miss it.
+            item("backdated", "Wed, 20 Nov 2024 08:00:00 +0000"),
+            item("old", "Wed, 20 Nov 2024 07:00:00 +0000"),
+        ];
+
+        let unseen = check_items(&items, &feed).await.unwrap();
+
+        assert_eq!(unseen.len(), 1);
+        assert_eq!(unseen[0].identity(), Some("backdated"));
+    }
+
+    #[tokio::test]
+    async fn test_check_items_brings_back_snoozed_items() {
+        let item = |guid: &str| {
+            FeedItem::from(
+                &ItemBuilder::default()
+                    .guid(rss::Guid {
+                        value: String::from(guid),
+                        permalink: false,
+                    })
+                    .pub_date(String::from("Wed, 20 Nov 2024 08:00:00 +0000"))
+                    .build(),
+            )
+        };
+
+        let mut feed =
+            FeedLinkData::new_for_testing("mock://test", "Wed, 20 Nov 2024 12:00:00 +0000");
+        feed.mark_items_seen(&[String::from("due"), String::from("later")]);
+        let now = chrono::Local::now().fixed_offset();
+        feed.snooze_item("due", now - chrono::Duration::minutes(1));
+        feed.snooze_item("later", now + chrono::Duration::hours(1));
+
+        let unseen = check_items(&[item("due"), item("later")], &feed)
+            .await
+            .unwrap();
+
+        assert_eq!(unseen.len(), 1);
+        assert_eq!(unseen[0].identity(), Some("due"));
+    }
+
+    #[tokio::test]
+    async fn test_check_items_without_history_uses_dates() {
+        let items = vec![FeedItem::from(
+            &ItemBuilder::default()
+                .link(String::from("https://example.com/old"))
+                .pub_date(String::from("Wed, 20 Nov 2024 08:00:00 +0000"))
+                .build(),
+        )];
+
+        let feed = FeedLinkData::new_for_testing("mock://test", "Wed, 20 Nov 2024 09:00:00 +0000");
+        let unseen = check_items(&items, &feed).await.unwrap();
 
-        assert_eq!(subject, String::from("My Feed, 5 unread items!"));
         assert!(
-            body.contains("Hello World"),
-            "Body should contain the title"
+            unseen.is_empty(),
+            "Nothing remembered yet, so old items should be treated as seen"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_items_ignores_order() {
+        let item = |guid: &str| {
+            FeedItem::from(
+                &ItemBuilder::default()
+                    .guid(rss::Guid {
+                        value: String::from(guid),
+                        permalink: false,
+                    })
+                    .build(),
+            )
+        };
+
+        let mut feed = FeedLinkData::new_for_testing("mock://test", "");
+        feed.mark_items_seen(&[String::from("pinned"), String::from("older")]);
+
+        // A pinned post at the top shouldn't hide the new ones below it.
+        let items = vec![item("pinned"), item("new-1"), item("older"), item("new-2")];
+        let unseen = check_items(&items, &feed).await.unwrap();
+
+        let ids: Vec<&str> = unseen.iter().filter_map(|i| i.identity()).collect();
+        assert_eq!(ids, vec!["new-1", "new-2"]);
+    }
+
+    #[test]
+    fn test_newest_item() {
+        let item = |title: &str, date: Option<&str>| {
+            FeedItem::from(
+                &ItemBuilder::default()
+                    .title(String::from(title))
+                    .pub_date(date.map(String::from))
+                    .build(),
+            )
+        };
+
+        let newest_first = vec![
+            item("newest", Some("Wed, 20 Nov 2024 10:00:00 +0000")),
+            item("older", Some("Wed, 20 Nov 2024 09:00:00 +0000")),
+        ];
+        let oldest_first: Vec<FeedItem> = newest_first.iter().rev().cloned().collect();
+        let undated = vec![item("first", None), item("second", None)];
+
+        assert_eq!(
+            newest_item(&newest_first).unwrap().title.as_deref(),
+            Some("newest")
+        );
+        assert_eq!(
+            newest_item(&oldest_first).unwrap().title.as_deref(),
+            Some("newest")
+        );
+        assert_eq!(
+            newest_item(&undated).unwrap().title.as_deref(),
+            Some("first")
         );
+        assert!(newest_item(&[]).is_none());
     }
 
     #[test]
@@ -283,13 +569,15 @@ mod tests {
 
     #[tokio::test]
     async fn test_check_all_feeds_inner_logic_mocked() {
-        let item = ItemBuilder::default()
-            .title(String::from("Brand New"))
-            .link(String::from("https://example.com/new"))
-            .pub_date(String::from("Wed, 20 Nov 2024 11:00:00 +0000"))
-            .build();
+        let item = FeedItem::from(
+            &ItemBuilder::default()
+                .title(String::from("Brand New"))
+                .link(String::from("https://example.com/new"))
+                .pub_date(String::from("Wed, 20 Nov 2024 11:00:00 +0000"))
+                .build(),
+        );
 
-        let channel = Channel {
+        let channel = Feed {
             title: String::from("Mock Feed"),
             items: vec![item.clone()],
             ..Default::default()
@@ -301,33 +589,361 @@ mod tests {
             String::from("Wed, 20 Nov 2024 10:00:00 +0000"),
         );
 
-        let unseen = check_items(channel.items(), feed_data.last_seen())
+        let unseen = check_items(channel.items(), &feed_data).await.unwrap();
+
+        assert_eq!(unseen.len(), 1);
+        assert_eq!(unseen.first().unwrap().title.as_deref(), Some("Brand New"));
+    }
+
+    #[test]
+    fn test_moved_by_self_link() {
+        let feed = |self_link: Option<&str>| Feed {
+            self_link: self_link.map(String::from),
+            ..Default::default()
+        };
+        let link = "https://example.com/feed";
+
+        assert_eq!(
+            moved_by_self_link(&feed(Some("https://example.org/feed.xml")), link),
+            Some(String::from("https://example.org/feed.xml"))
+        );
+        assert_eq!(moved_by_self_link(&feed(Some(link)), link), None);
+        assert_eq!(
+            moved_by_self_link(&feed(Some("https://example.com")), "https://example.com/"),
+            None,
+            "Same address once normalised"
+        );
+        assert_eq!(moved_by_self_link(&feed(Some("/feed.xml")), link), None);
+        assert_eq!(moved_by_self_link(&feed(None), link), None);
+    }
+
+    // Remembers what it was asked to deliver, and optionally fails.
+    struct RecordingNotifier {
+        fail: bool,
+        received: Arc<Mutex<Vec<NotificationData>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Notifier for RecordingNotifier {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn notify(&self, notification: &NotificationData) -> Result<()> {
+            self.received.lock().unwrap().push(notification.clone());
+            if self.fail {
+                return Err(Error::notify(self.name(), "refused"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_all_feeds_dispatches_to_every_notifier() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0"?><rss version="2.0"><channel><title>Mock Feed</title><link>https://example.com/</link><description>test</description>
+                <item><title>Old</title><guid>old</guid><pubDate>Wed, 20 Nov 2024 09:00:00 +0000</pubDate></item>
+                <item><title>New</title><guid>new</guid><pubDate>Wed, 20 Nov 2024 10:30:00 +0000</pubDate></item>
+                <item><title>Newer</title><guid>newer</guid><pubDate>Wed, 20 Nov 2024 11:00:00 +0000</pubDate></item>
+                </channel></rss>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let feeds = vec![FeedLinkData::new(
+            server.uri(),
+            String::from("* * * * *"),
+            String::from("Wed, 20 Nov 2024 10:00:00 +0000"),
+        )];
+        let failed: Arc<Mutex<Vec<NotificationData>>> = Default::default();
+        let delivered: Arc<Mutex<Vec<NotificationData>>> = Default::default();
+        let notifiers: Vec<Box<dyn Notifier>> = vec![
+            Box::new(RecordingNotifier {
+                fail: true,
+                received: failed.clone(),
+            }),
+            Box::new(RecordingNotifier {
+                fail: false,
+                received: delivered.clone(),
+            }),
+        ];
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let results = check_all_feeds_and_notify(&feeds, &fetcher, &notifiers, |feed| Templates {
+            subject: Some(format!("{{{{latest_title}}}} from {}", feed.feed_link())),
+            body: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(results.checked.len(), 1);
+        assert_eq!(results.checked[0].unseen_items_count, 2);
+
+        assert_eq!(failed.lock().unwrap().len(), 1);
+        let delivered = delivered.lock().unwrap();
+        assert_eq!(
+            delivered.len(),
+            1,
+            "A failing notifier doesn't stop the next"
+        );
+
+        let titles: Vec<_> = delivered[0]
+            .items
+            .iter()
+            .map(|item| item.title.as_deref().unwrap())
+            .collect();
+        assert_eq!(titles, vec!["Newer", "New"], "Newest first");
+        assert_eq!(delivered[0].title, "Mock Feed");
+        assert_eq!(delivered[0].feed_link, server.uri());
+        assert_eq!(
+            delivered[0].create_subject(),
+            format!("Newer from {}", server.uri()),
+            "Templates are looked up by feed"
+        );
+        assert_eq!(
+            delivered[0].feed_icon.as_deref(),
+            Some("https://example.com/favicon.ico"),
+            "Without an icon, the site's favicon"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_all_feeds_skips_muted_feeds() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0"?><rss version="2.0"><channel><title>Mock Feed</title><link>https://example.com/</link><description>test</description>
+                <item><title>New</title><guid>new</guid><pubDate>Wed, 20 Nov 2024 10:30:00 +0000</pubDate></item>
+                </channel></rss>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let mut feed = FeedLinkData::new(
+            server.uri(),
+            String::from("* * * * *"),
+            String::from("Wed, 20 Nov 2024 10:00:00 +0000"),
+        );
+        feed.set_muted(true);
+        let feeds = vec![feed];
+        let delivered: Arc<Mutex<Vec<NotificationData>>> = Default::default();
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(RecordingNotifier {
+            fail: false,
+            received: delivered.clone(),
+        })];
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let results =
+            check_all_feeds_and_notify(&feeds, &fetcher, &notifiers, |_| Templates::default())
+                .await
+                .unwrap();
+
+        assert_eq!(results.checked.len(), 1);
+        assert_eq!(
+            results.checked[0].item_ids,
+            vec![String::from("new")],
+            "Muted feeds are still remembered as seen"
+        );
+        assert!(delivered.lock().unwrap().is_empty());
+    }
+
+    fn feed_with_self_link(self_link: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?><rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel><title>Mock Feed</title><link>https://example.com/</link><description>test</description>
+            <atom:link href="{}" rel="self" type="application/rss+xml"/>
+            </channel></rss>"#,
+            self_link
+        )
+    }
+
+    async fn moved_to(feed: &FeedLinkData) -> Option<String> {
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let results =
+            check_all_feeds_and_notify(std::slice::from_ref(feed), &fetcher, &Vec::new(), |_| {
+                Templates::default()
+            })
             .await
             .unwrap();
+        results.checked[0].moved_to.clone()
+    }
 
-        assert_eq!(unseen.len(), 1);
-        assert_eq!(unseen.first().unwrap().title().unwrap(), "Brand New");
+    #[tokio::test]
+    async fn test_check_all_feeds_follows_only_changed_self_links() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let feed_link = format!("{}/feed", server.uri());
+        let stale_link = format!("{}/old-feed", server.uri());
+        let new_link = format!("{}/new-feed", server.uri());
+        Mock::given(method("GET"))
+            .and(path("/feed"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(feed_with_self_link(&stale_link)),
+            )
+            .mount(&server)
+            .await;
+        // The stale address sends readers back, which mustn't ping-pong.
+        Mock::given(method("GET"))
+            .and(path("/old-feed"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", feed_link.as_str()))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/new-feed"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(feed_with_self_link(&new_link)),
+            )
+            .mount(&server)
+            .await;
+
+        let mut feed = FeedLinkData::new(
+            feed_link.clone(),
+            String::from("* * * * *"),
+            String::from("Wed, 20 Nov 2024 10:00:00 +0000"),
+        );
+        assert_eq!(moved_to(&feed).await, None, "First sighting is only noted");
+
+        feed.set_self_link(Some(String::from("https://example.com/feed")));
+        assert_eq!(
+            moved_to(&feed).await,
+            None,
+            "A changed self link that redirects back isn't followed"
+        );
+
+        feed.set_self_link(Some(stale_link.clone()));
+        assert_eq!(
+            moved_to(&feed).await,
+            None,
+            "An unchanged self link is ignored"
+        );
+
+        Mock::given(method("GET"))
+            .and(path("/feed"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(feed_with_self_link(&new_link)),
+            )
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        assert_eq!(moved_to(&feed).await, Some(new_link));
     }
 
     #[tokio::test]
-    #[ignore] // prevents cargo test from running it by default
-    async fn test_actual_notification() {
-        // Build a fake RSS item
-        let mut item = Item::default();
-        item.set_title(Some("Test Notification Title".into()));
-        item.set_link(Some("https://example.com".into()));
-
-        // Build NotificationData
-        let notif = NotificationData {
-            title: "Mock Feed".into(),
-            unseen_items_count: 1,
-            latest_item: item,
-        };
+    async fn test_check_all_feeds_brings_back_snoozes_despite_304() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0"?><rss version="2.0"><channel><title>Mock Feed</title><link>https://example.com/</link><description>test</description>
+                <item><title>Later</title><guid>later</guid><pubDate>Wed, 20 Nov 2024 10:30:00 +0000</pubDate></item>
+                </channel></rss>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let mut feed = FeedLinkData::new(
+            server.uri(),
+            String::from("* * * * *"),
+            String::from("Wed, 20 Nov 2024 11:00:00 +0000"),
+        );
+        feed.mark_items_seen(&[String::from("later")]);
+        feed.set_http_cache(HttpCache {
+            etag: Some(String::from("\"v1\"")),
+            last_modified: None,
+        });
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+
+        feed.snooze_item(
+            "later",
+            chrono::Local::now().fixed_offset() + chrono::Duration::hours(1),
+        );
+        let results =
+            check_all_feeds_and_notify(std::slice::from_ref(&feed), &fetcher, &Vec::new(), |_| {
+                Templates::default()
+            })
+            .await
+            .unwrap();
+        assert!(results.checked[0].http_cache.is_none(), "Not modified");
+
+        let now = chrono::Local::now().fixed_offset();
+        feed.snooze_item("later", now - chrono::Duration::minutes(1));
+        feed.snooze_item("gone", now - chrono::Duration::minutes(1));
+        feed.snooze_item("gone-later", now + chrono::Duration::hours(1));
+        let results =
+            check_all_feeds_and_notify(std::slice::from_ref(&feed), &fetcher, &Vec::new(), |_| {
+                Templates::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            results.checked[0].unsnoozed_ids,
+            vec![String::from("later"), String::from("gone")],
+            "Due snoozes of items no longer in the feed are dropped too"
+        );
+
+        let unsnoozed_ids = results.checked[0].unsnoozed_ids.clone();
+        feed.unsnooze_items(&unsnoozed_ids);
+        let results =
+            check_all_feeds_and_notify(std::slice::from_ref(&feed), &fetcher, &Vec::new(), |_| {
+                Templates::default()
+            })
+            .await
+            .unwrap();
+        assert!(
+            results.checked[0].http_cache.is_none(),
+            "Back to conditional requests"
+        );
+    }
 
-        // This should trigger a real desktop notification.
-        notif.send_notify().expect("Notification failed");
+    #[tokio::test]
+    async fn test_get_feed() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0"?><rss version="2.0"><channel><title>Mock Feed</title><link>https://example.com/</link><description>test</description></channel></rss>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(&FetchConfig::default()).unwrap();
+        let feed = get_feed(&fetcher, &server.uri(), &HttpCache::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(feed.title(), "Mock Feed");
 
-        // Keep test alive for a moment so user can see it
-        std::thread::sleep(std::time::Duration::from_secs(3));
+        let cache = HttpCache {
+            etag: Some(String::from("\"v1\"")),
+            last_modified: None,
+        };
+        assert!(
+            get_feed(&fetcher, &server.uri(), &cache)
+                .await
+                .unwrap()
+                .is_none()
+        );
     }
 }
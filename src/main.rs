@@ -1,94 +1,386 @@
+use chrono::Local;
+use reqwest::Url;
 use std::env;
+use std::time::Duration;
 
 use rss_notify::check_all_feeds_and_notify;
-// use rss_notify::config::Config;
-use rss_notify::data::Data;
+use rss_notify::config::{Config, DesktopConfig, JsonlConfig, NotifierConfig};
+use rss_notify::data::{Data, FeedLinkData, HttpCache, parse_frequency};
+use rss_notify::fetch::{FetchedFeed, Fetcher};
+use rss_notify::notifier::{Notifier, build_notifiers};
+
+// Longest the daemon sleeps before re-reading data.toml, so feeds added or
+// removed from another shell get picked up.
+const DAEMON_MAX_SLEEP: Duration = Duration::from_secs(60);
+// First pause after a check that couldn't finish, doubled while they keep
+// failing, so the same due feeds aren't fetched again straight away.
+const DAEMON_ERROR_SLEEP: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 enum ArgumentOptions {
     Check,
+    Daemon,
     Add,
     Remove,
+    Mute,
+    Unmute,
     List,
     Help,
 }
 
 impl ArgumentOptions {
-    async fn execute(&self, args: &[String], data: &mut Data) {
+    async fn execute(
+        &self,
+        args: &[String],
+        config: &Config,
+        data: &mut Data,
+    ) -> Result<(), String> {
         match self {
-            ArgumentOptions::Check => run_check(data).await.expect("WHAT"),
-            ArgumentOptions::Add => run_add(args, data).expect("WHAT"),
-            ArgumentOptions::Remove => run_remove(args, data).expect("WHAT"),
-            ArgumentOptions::List => run_list(data),
-            ArgumentOptions::Help => run_help(),
+            ArgumentOptions::Check => run_check(args, config, data).await,
+            ArgumentOptions::Daemon => run_daemon(args, config, data).await,
+            ArgumentOptions::Add => run_add(args, config, data).await,
+            ArgumentOptions::Remove => run_remove(args, data),
+            ArgumentOptions::Mute => run_mute(args, data, true),
+            ArgumentOptions::Unmute => run_mute(args, data, false),
+            ArgumentOptions::List => {
+                run_list(data);
+                Ok(())
+            }
+            ArgumentOptions::Help => {
+                run_help();
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn run_check(args: &[String], config: &Config, data: &mut Data) -> Result<(), String> {
+    let force = args.iter().skip(2).any(|arg| arg == "--force");
+
+    let mut feed_link_data_list = Vec::new();
+    for feed in data.get_all_feed_link_data() {
+        if force {
+            feed_link_data_list.push(feed);
+            continue;
+        }
+        match feed.is_frequency_check_due() {
+            Ok(true) => {
+                feed_link_data_list.push(feed);
+                continue;
+            }
+            Ok(false) => (),
+            Err(e) => {
+                eprintln!("Skipping {}: {}", feed.feed_link(), e.report());
+                continue;
+            }
+        }
+
+        let next = feed
+            .next_check_due()
+            .ok()
+            .flatten()
+            .map(|next| next.with_timezone(&Local).to_rfc2822())
+            .unwrap_or_default();
+        if feed.consecutive_failures() > 0 {
+            eprintln!(
+                "Skipping {}, backing off after {} failures ({}), next check due {}",
+                feed.feed_link(),
+                feed.consecutive_failures(),
+                feed.last_error().unwrap_or("unknown error"),
+                next
+            );
+        } else {
+            eprintln!("Skipping {}, next check due {}", feed.feed_link(), next);
         }
     }
+
+    let fetcher = Fetcher::new(&config.fetch)
+        .map_err(|e| format!("Failed to set up fetcher: {}", e.report()))?;
+    let notifiers = build_notifiers(&select_notifiers(args, config)?, &config.fetch)
+        .map_err(|e| format!("Failed to set up notifiers: {}", e.report()))?;
+    let result = check_feeds(&feed_link_data_list, &fetcher, &notifiers, config, data).await;
+
+    // Seen items are saved by now, so nothing is lost if the popups are left open.
+    for notifier in &notifiers {
+        notifier.wait().await;
+    }
+    result
 }
 
-async fn run_check(data: &mut Data) -> Result<(), String> {
-    let feed_link_data_list = data.get_all_feed_link_data();
-    let unseen_feeds = check_all_feeds_and_notify(&feed_link_data_list).await;
+async fn check_feeds(
+    feeds: &[FeedLinkData],
+    fetcher: &Fetcher,
+    notifiers: &[Box<dyn Notifier>],
+    config: &Config,
+    data: &mut Data,
+) -> Result<(), String> {
+    let results = check_all_feeds_and_notify(feeds, fetcher, notifiers, |feed| {
+        config.templates_for(&feed.links())
+    })
+    .await;
 
-    let unseen_feeds: Vec<&str> = match unseen_feeds {
-        Ok(feeds) => feeds,
+    let results = match results {
+        Ok(results) => results,
         Err(e) => {
-            return Err(format!("Error Checking: {}", e));
+            return Err(format!("Error Checking: {}", e.report()));
         }
     };
 
-    for feed in &unseen_feeds {
-        data.update_link_map(feed);
+    // Read again, as the fetches can take a while: feeds added, removed or muted
+    // from another shell meanwhile are kept, and only these feeds' results go in.
+    *data = Data::load(None).map_err(|e| format!("Failed to load data: {}", e.report()))?;
+
+    // Failed fetches count as checked too, so a broken feed waits for its next
+    // scheduled time instead of being retried straight away.
+    for feed in feeds {
+        data.update_last_checked(feed.feed_link());
+    }
+
+    for feed in results.failed {
+        data.record_failure(
+            feed.feed_link,
+            &feed.error,
+            feed.retry_after,
+            fetcher.config(),
+        );
     }
 
-    data.save(None).expect("Didn't save...");
-    Ok(())
+    for feed in results.checked {
+        data.update_link_map(feed.feed_link, &feed.item_ids, feed.newest_item_date);
+        data.unsnooze_items(feed.feed_link, &feed.unsnoozed_ids);
+        if let Some(http_cache) = feed.http_cache {
+            data.update_http_cache(feed.feed_link, http_cache);
+        }
+        if let Some(self_link) = &feed.self_link {
+            data.update_self_link(feed.feed_link, self_link);
+        }
+        if let Some(new_link) = &feed.moved_to {
+            eprintln!("Feed moved: {} -> {}", feed.feed_link, new_link);
+            data.migrate_link_map(feed.feed_link, new_link);
+        }
+    }
+
+    data.save(None)
+        .map_err(|e| format!("Failed to save data: {}", e.report()))
+}
+
+// `--notifier <type>`, which can be repeated, picks notifiers by type instead
+// of using every configured one. desktop and jsonl (to stdout) don't need to be
+// configured first.
+fn select_notifiers(args: &[String], config: &Config) -> Result<Vec<NotifierConfig>, String> {
+    let kinds: Vec<&str> = args
+        .windows(2)
+        .filter(|pair| pair[0] == "--notifier")
+        .map(|pair| pair[1].as_str())
+        .collect();
+    if kinds.is_empty() {
+        return Ok(config.notifiers.clone());
+    }
+
+    let mut selected = Vec::new();
+    for kind in kinds {
+        let configured: Vec<NotifierConfig> = config
+            .notifiers
+            .iter()
+            .filter(|notifier| notifier.kind() == kind)
+            .cloned()
+            .collect();
+        if !configured.is_empty() {
+            selected.extend(configured);
+            continue;
+        }
+
+        match kind {
+            "desktop" => selected.push(NotifierConfig::Desktop(DesktopConfig::default())),
+            "jsonl" => selected.push(NotifierConfig::Jsonl(JsonlConfig::default())),
+            _ => return Err(format!("No {} notifier is configured", kind)),
+        }
+    }
+    Ok(selected)
+}
+
+async fn run_daemon(args: &[String], config: &Config, data: &mut Data) -> Result<(), String> {
+    let fetcher = Fetcher::new(&config.fetch)
+        .map_err(|e| format!("Failed to set up fetcher: {}", e.report()))?;
+    let notifiers = build_notifiers(&select_notifiers(args, config)?, &config.fetch)
+        .map_err(|e| format!("Failed to set up notifiers: {}", e.report()))?;
+    eprintln!("Watching feeds, press Ctrl+C to stop.");
+
+    let mut failed_checks: u32 = 0;
+    loop {
+        *data = Data::load(None).map_err(|e| format!("Failed to load data: {}", e.report()))?;
+
+        let due_feeds: Vec<FeedLinkData> = data
+            .get_all_feed_link_data()
+            .into_iter()
+            .filter(|feed| match feed.is_frequency_check_due() {
+                Ok(due) => due,
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", feed.feed_link(), e.report());
+                    false
+                }
+            })
+            .collect();
+
+        if !due_feeds.is_empty() {
+            match check_feeds(&due_feeds, &fetcher, &notifiers, config, data).await {
+                Ok(()) => failed_checks = 0,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failed_checks = failed_checks.saturating_add(1);
+                }
+            }
+        }
+
+        let next_due = data
+            .link_map()
+            .values()
+            .filter_map(|feed| feed.next_check_due().ok().flatten())
+            .min();
+        let sleep_for = match next_due {
+            Some(next) => (next.with_timezone(&Local) - Local::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+                .min(DAEMON_MAX_SLEEP),
+            None => DAEMON_MAX_SLEEP,
+        };
+        let sleep_for = match failed_checks {
+            0 => sleep_for,
+            failed => DAEMON_ERROR_SLEEP
+                .saturating_mul(2u32.saturating_pow(failed - 1))
+                .min(DAEMON_MAX_SLEEP),
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(sleep_for) => {}
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("Stopping.");
+                return Ok(());
+            }
+        }
+    }
 }
 
 fn run_list(data: &Data) {
     let data_map = data.link_map();
 
-    for feed_link_data in data_map.keys() {
-        println!("{}", feed_link_data);
+    for (feed_link, feed_link_data) in data_map {
+        if feed_link_data.is_muted() {
+            println!("{} (muted)", feed_link);
+        } else {
+            println!("{}", feed_link);
+        }
     }
 }
 
-fn run_add(args: &[String], data: &mut Data) -> Result<(), String> {
-    let link = args.get(2).ok_or("No link provided")?.clone();
-    let frequency = args.get(3).ok_or("No frequency provided")?.clone();
+async fn run_add(args: &[String], config: &Config, data: &mut Data) -> Result<(), String> {
+    let no_verify = args.iter().skip(2).any(|arg| arg == "--no-verify");
+    let mut positional = args.iter().skip(2).filter(|arg| *arg != "--no-verify");
+    let requested = positional.next().ok_or("No link provided")?.clone();
+    let mut link = requested.clone();
+    let frequency = positional.next().ok_or("No frequency provided")?.clone();
+
+    parse_frequency(&frequency).map_err(|e| e.report())?;
+    let url = Url::parse(&link).map_err(|e| format!("Invalid link {}: {}", link, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "Invalid link {}: only http and https are supported",
+            link
+        ));
+    }
+
+    if no_verify {
+        println!("Adding {} without checking it", link);
+    } else {
+        let fetcher = Fetcher::new(&config.fetch)
+            .map_err(|e| format!("Failed to set up fetcher: {}", e.report()))?;
+        let fetched = fetcher
+            .fetch(&link, &HttpCache::default())
+            .await
+            .map_err(|e| {
+                format!(
+                    "Couldn't read a feed from {}: {} (add --no-verify to add it anyway)",
+                    link,
+                    e.report()
+                )
+            })?;
+
+        // A web page link is swapped for the feed it advertises.
+        if let Some(feed_link) = fetched.moved_to() {
+            println!("Found feed {} at {}", feed_link, link);
+            link = feed_link.to_string();
+        }
+        if let FetchedFeed::Modified { feed, .. } = &fetched {
+            println!(
+                "Adding \"{}\" with {} items",
+                feed.title(),
+                feed.items().len()
+            );
+        }
+    }
 
     data.insert_link_map(&link, &frequency);
-    data.save(None).expect("Didn't save...");
-    Ok(())
+    // Kept so [[feeds]] settings given for the page still find the feed.
+    if link != requested {
+        data.add_previous_link(&link, &requested);
+    }
+    data.save(None)
+        .map_err(|e| format!("Failed to save data: {}", e.report()))
 }
 
 fn run_remove(args: &[String], data: &mut Data) -> Result<(), String> {
     let link = args.get(2).ok_or("No link provided")?.clone();
 
     data.remove_link_map(&link);
-    data.save(None).expect("Didn't save...");
-    Ok(())
+    data.save(None)
+        .map_err(|e| format!("Failed to save data: {}", e.report()))
+}
+
+fn run_mute(args: &[String], data: &mut Data, muted: bool) -> Result<(), String> {
+    let link = args.get(2).ok_or("No link provided")?;
+
+    data.set_muted(link, muted)
+        .ok_or_else(|| format!("Not subscribed to {}", link))?;
+    data.save(None)
+        .map_err(|e| format!("Failed to save data: {}", e.report()))
 }
 
 fn run_help() {
     println!(
         r#"Available commands:
 
-        check
-            Checks if any subscribed feed has unseen items.
+        check [--force] [--notifier <type>]...
+            Checks if any subscribed feed that is due has unseen items.
+            Arguments:
+                --force     Check every feed, ignoring their frequency and any backoff.
+                --notifier  Only notify through notifiers of this type (desktop, webhook, email,
+                            ntfy, gotify, jsonl or exec) instead of every configured one. jsonl writes
+                            JSON Lines to stdout unless one is configured with a path.
+
+        daemon [--notifier <type>]...
+            Stay running and check each feed whenever its frequency is due.
 
         list
             View all subscribed feeds.
 
-        add <link> <frequency>
-            Add a new feed to the config.
+        add <link> <frequency> [--no-verify]
+            Add a new feed to the config, after checking that it can be fetched and read.
             Arguments:
-                link    The RSS/Atom feed URL.
+                link    The RSS/Atom/JSON feed URL, or a web page that links to one.
                 frequency   How often to check the feed in cron-like format (e.g., \"* * * * *\")
+                --no-verify     Add the feed without fetching it, e.g. while offline.
 
         remove <link>
             Remove a feed from the config.
             Arguments:
-                link    The RSS/Atom feed URL."#
+                link    The RSS/Atom/JSON feed URL.
+
+        mute <link>
+        unmute <link>
+            Stop or start notifying about a feed. Muted feeds are still checked, so
+            unmuting doesn't bring back everything posted in between.
+            Arguments:
+                link    The RSS/Atom/JSON feed URL."#
     )
 }
 
@@ -106,8 +398,11 @@ impl Argument {
 
         let option = match arg {
             "check" => ArgumentOptions::Check,
+            "daemon" => ArgumentOptions::Daemon,
             "add" => ArgumentOptions::Add,
             "remove" => ArgumentOptions::Remove,
+            "mute" => ArgumentOptions::Mute,
+            "unmute" => ArgumentOptions::Unmute,
             "list" => ArgumentOptions::List,
             "help" => ArgumentOptions::Help,
             _ => return Err(format!("Invalid command: {}", arg)),
@@ -121,12 +416,25 @@ impl Argument {
 
 #[tokio::main]
 async fn main() {
-    // let config: Config = Config::load(None).expect("Failed to load config");
-    let mut data: Data = Data::load(None).expect("Failed to load data");
+    let loaded = Config::load(None).and_then(|config| Ok((config, Data::load(None)?)));
+    let (config, mut data) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e.report());
+            std::process::exit(1);
+        }
+    };
 
     let args: Vec<String> = env::args().collect();
 
     let argument = Argument::new(&args).expect("Invalid command");
 
-    argument.specified_option.execute(&args, &mut data).await;
+    if let Err(e) = argument
+        .specified_option
+        .execute(&args, &config, &mut data)
+        .await
+    {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
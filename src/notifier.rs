@@ -0,0 +1,456 @@
+use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Url};
+use serde_json::{Value, json};
+use std::env;
+
+use crate::config::{FetchConfig, NotifierConfig, Templates};
+use crate::error::{Error, Result};
+use crate::feed::FeedItem;
+use crate::fetch::parse_retry_after;
+use crate::newest_item;
+
+pub mod desktop;
+pub mod email;
+pub mod exec;
+pub mod gotify;
+pub mod jsonl;
+pub mod ntfy;
+pub mod webhook;
+
+pub use desktop::DesktopNotifier;
+pub use email::EmailNotifier;
+pub use exec::ExecNotifier;
+pub use gotify::GotifyNotifier;
+pub use jsonl::JsonlNotifier;
+pub use ntfy::NtfyNotifier;
+pub use webhook::WebhookNotifier;
+
+const DEFAULT_SUBJECT: &str = "{{feed_title}}, {{unseen_count}} unread items!";
+const DEFAULT_BODY: &str = "Latest Item: <i>{{latest_title}}</i>\nClick to read more!";
+// Wide enough that html2text never wraps a summary; notifications wrap themselves.
+const SUMMARY_TEXT_WIDTH: usize = 10_000;
+
+// Every placeholder a template can use. Known ones without a value, like the
+// author of an item that doesn't name one, are left empty.
+pub const PLACEHOLDERS: &[&str] = &[
+    "feed_title",
+    "feed_url",
+    "unseen_count",
+    "latest_title",
+    "latest_link",
+    "latest_date",
+    "latest_author",
+    "latest_categories",
+    "latest_summary",
+    "latest_summary_text",
+];
+
+// A feed's new items, newest first, handed to every configured notifier.
+#[derive(Debug, Clone)]
+pub struct NotificationData {
+    pub title: String,
+    pub feed_link: String,
+    // the feed's icon, or where its site's favicon should be
+    pub feed_icon: Option<String>,
+    pub items: Vec<FeedItem>,
+    // the feed's templates, or none for the built-in wording
+    pub templates: Templates,
+}
+
+impl NotificationData {
+    pub fn latest_item(&self) -> Option<&FeedItem> {
+        newest_item(&self.items)
+    }
+
+    pub fn unseen_items_count(&self) -> usize {
+        self.items.len()
+    }
+
+    // Subjects are shown as text everywhere, so any markup is dropped.
+    pub fn create_subject(&self) -> String {
+        let template = self.templates.subject.as_deref().unwrap_or(DEFAULT_SUBJECT);
+        self.render(&strip_tags(template), String::from)
+    }
+
+    // For desktop notifications, which understand a little markup, so values
+    // are escaped to keep an item title from being read as tags.
+    pub fn create_body(&self) -> String {
+        let template = self.templates.body.as_deref().unwrap_or(DEFAULT_BODY);
+        self.render(template, |value| escape_markup(&value))
+    }
+
+    // create_body without the markup, for services that show text as is.
+    pub fn create_plain_body(&self) -> String {
+        let template = self.templates.body.as_deref().unwrap_or(DEFAULT_BODY);
+        self.render(&strip_tags(template), String::from)
+    }
+
+    fn render(&self, template: &str, escape: impl Fn(String) -> String) -> String {
+        fill_placeholders(template, |name| {
+            if !PLACEHOLDERS.contains(&name) {
+                return None;
+            }
+            Some(escape(self.placeholder(name).unwrap_or_default()))
+        })
+    }
+
+    // The document machine-readable notifiers send.
+    pub fn to_json(&self) -> Value {
+        let items: Vec<Value> = self
+            .items
+            .iter()
+            .map(|item| {
+                json!({
+                    "title": item.title,
+                    "link": item.link,
+                    "date": item.date().map(|date| date.to_rfc3339()),
+                    "summary": item.summary,
+                })
+            })
+            .collect();
+
+        json!({
+            "feed_title": self.title,
+            "feed_url": self.feed_link,
+            "unseen_count": self.unseen_items_count(),
+            "items": items,
+        })
+    }
+
+    // A single item as a standalone record, with the feed it came from.
+    pub fn item_record(&self, item: &FeedItem) -> Value {
+        json!({
+            "feed": self.title,
+            "feed_url": self.feed_link,
+            "title": item.title,
+            "link": item.link,
+            "guid": item.id,
+            "published": item.date().map(|date| date.to_rfc3339()),
+            "summary": item.summary,
+        })
+    }
+
+    // Plain-text values for {{name}} placeholders in notifier templates.
+    pub fn placeholder(&self, name: &str) -> Option<String> {
+        let latest = self.latest_item();
+        let value = match name {
+            "feed_title" => self.title.clone(),
+            "feed_url" => self.feed_link.clone(),
+            "unseen_count" => self.unseen_items_count().to_string(),
+            "latest_title" => latest
+                .and_then(|item| item.title.clone())
+                .unwrap_or_else(|| String::from("Untitled")),
+            "latest_link" => latest.and_then(|item| item.link.clone())?,
+            "latest_date" => latest.and_then(|item| item.date())?.to_rfc3339(),
+            "latest_author" => latest.and_then(|item| item.author.clone())?,
+            "latest_categories" => {
+                Some(latest?.categories.join(", ")).filter(|categories| !categories.is_empty())?
+            }
+            "latest_summary" => latest.and_then(|item| item.summary.clone())?,
+            "latest_summary_text" => {
+                let summary = latest.and_then(|item| item.summary.as_deref())?;
+                html2text::from_read(summary.as_bytes(), SUMMARY_TEXT_WIDTH)
+                    .map(|text| text.trim().to_string())
+                    .unwrap_or_else(|_| summary.to_string())
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+// A value from the config, or from the environment variable it names instead.
+pub(crate) fn config_or_env(
+    value: &Option<String>,
+    env_name: &Option<String>,
+) -> Result<Option<String>> {
+    match env_name {
+        Some(name) => env::var(name)
+            .map(Some)
+            .map_err(|e| Error::config(format!("Couldn't read ${}", name), e)),
+        None => Ok(value.clone()),
+    }
+}
+
+// Sends a request to a notification service, turning error responses into
+// HttpStatus errors.
+pub(crate) async fn send_request(request: RequestBuilder, url: &Url) -> Result<()> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Error::request(url.as_str(), e))?;
+
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        return Err(Error::HttpStatus {
+            url: url.to_string(),
+            status,
+            retry_after,
+        });
+    }
+    Ok(())
+}
+
+// Desktop notification markup is a small subset of HTML, and HTML mail is
+// escaped the same way. Quotes too, as values can end up in attributes.
+pub(crate) fn escape_markup(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Drops <tags> from a template meant for markup, leaving its text (and any
+// {{placeholders}}) alone.
+fn strip_tags(template: &str) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        match rest[start..].find('>') {
+            Some(end) => rest = &rest[start + end + 1..],
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        }
+    }
+    text.push_str(rest);
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+// Replaces each {{name}} with lookup(name). Unknown placeholders are left as
+// they are, so a typo shows up in the output rather than vanishing.
+pub fn fill_placeholders(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + end + 2];
+        output.push_str(&rest[..start]);
+        match lookup(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(placeholder),
+        }
+        rest = &rest[start + end + 2..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+// A delivery channel for new items. Notifiers are called one after another for
+// each feed, and a failing one doesn't stop the rest.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    // Short name used in logs and errors, e.g. "desktop".
+    fn name(&self) -> &str;
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()>;
+
+    // Called once every feed in a check has been notified, for notifiers that
+    // batch what they were given.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // Waits for anything left running after a check, like popups nobody has
+    // answered yet. Called only once the check's results have been saved.
+    async fn wait(&self) {}
+}
+
+pub fn build_notifiers(
+    configs: &[NotifierConfig],
+    fetch: &FetchConfig,
+) -> Result<Vec<Box<dyn Notifier>>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+    for config in configs {
+        let notifier: Box<dyn Notifier> = match config {
+            NotifierConfig::Desktop(desktop) => Box::new(DesktopNotifier::new(desktop, fetch)?),
+            NotifierConfig::Webhook(webhook) => Box::new(WebhookNotifier::new(webhook)?),
+            NotifierConfig::Email(email) => Box::new(EmailNotifier::new(email)?),
+            NotifierConfig::Ntfy(ntfy) => Box::new(NtfyNotifier::new(ntfy)?),
+            NotifierConfig::Gotify(gotify) => Box::new(GotifyNotifier::new(gotify)?),
+            NotifierConfig::Jsonl(jsonl) => Box::new(JsonlNotifier::new(jsonl)?),
+            NotifierConfig::Exec(exec) => Box::new(ExecNotifier::new(exec)?),
+        };
+        notifiers.push(notifier);
+    }
+
+    Ok(notifiers)
+}
+
+// The notification every notifier's tests start from.
+#[cfg(test)]
+pub(crate) fn test_notification(title: &str, items: Vec<FeedItem>) -> NotificationData {
+    NotificationData {
+        title: String::from(title),
+        feed_link: String::from("https://example.com/feed"),
+        feed_icon: None,
+        templates: Templates::default(),
+        items,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DesktopConfig;
+    use chrono::DateTime;
+
+    fn item(title: &str, date: &str) -> FeedItem {
+        FeedItem {
+            title: Some(title.into()),
+            published: DateTime::parse_from_rfc2822(date).ok(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_notification_create_strings() {
+        let notif = test_notification(
+            "My Feed",
+            vec![
+                item("Older", "Wed, 20 Nov 2024 10:00:00 +0000"),
+                item("Hello World", "Wed, 20 Nov 2024 11:00:00 +0000"),
+            ],
+        );
+
+        let subject = notif.create_subject();
+        let body = notif.create_body();
+
+        assert_eq!(subject, String::from("My Feed, 2 unread items!"));
+        assert!(
+            body.contains("Hello World"),
+            "Body should contain the newest item's title"
+        );
+    }
+
+    #[test]
+    fn test_notification_templates() {
+        let notif = NotificationData {
+            templates: Templates {
+                subject: Some(String::from("<b>{{feed_title}}</b>: {{latest_title}}")),
+                body: Some(String::from(
+                    "<i>{{latest_title}}</i> by {{latest_author}} [{{latest_categories}}]\n{{latest_summary_text}} {{nope}}",
+                )),
+            },
+            ..test_notification(
+                "Tom & Jerry",
+                vec![FeedItem {
+                    title: Some(String::from("1 < 2")),
+                    categories: vec![String::from("maths"), String::from("news")],
+                    summary: Some(String::from("<p>Some <b>bold</b> news</p>")),
+                    ..Default::default()
+                }],
+            )
+        };
+
+        assert_eq!(notif.create_subject(), "Tom & Jerry: 1 < 2");
+        assert_eq!(
+            notif.create_body(),
+            "<i>1 &lt; 2</i> by  [maths, news]\nSome bold news {{nope}}",
+            "Values escaped for markup, unknown placeholders kept"
+        );
+        assert_eq!(
+            notif.create_plain_body(),
+            "1 < 2 by  [maths, news]\nSome bold news {{nope}}"
+        );
+
+        assert_eq!(strip_tags("a &lt;b&gt; <i>c</i> <"), "a <b> c <");
+
+        let linked = NotificationData {
+            templates: Templates {
+                subject: None,
+                body: Some(String::from(
+                    r#"<a href="{{latest_link}}">{{latest_title}}</a>"#,
+                )),
+            },
+            ..test_notification(
+                "Feed",
+                vec![FeedItem {
+                    title: Some(String::from("Rock 'n' roll")),
+                    link: Some(String::from(r#"https://example.com/?q="x""#)),
+                    ..Default::default()
+                }],
+            )
+        };
+        assert_eq!(
+            linked.create_body(),
+            r#"<a href="https://example.com/?q=&quot;x&quot;">Rock &#39;n&#39; roll</a>"#
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let notif = test_notification(
+            "My Feed",
+            vec![item("Hello World", "Wed, 20 Nov 2024 11:00:00 +0000")],
+        );
+
+        assert_eq!(
+            notif.to_json(),
+            json!({
+                "feed_title": "My Feed",
+                "feed_url": "https://example.com/feed",
+                "unseen_count": 1,
+                "items": [{
+                    "title": "Hello World",
+                    "link": null,
+                    "date": "2024-11-20T11:00:00+00:00",
+                    "summary": null,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_fill_placeholders() {
+        let lookup = |name: &str| (name == "feed_title").then(|| String::from("My Feed"));
+
+        assert_eq!(
+            fill_placeholders("New in {{feed_title}}: {{ feed_title }}!", lookup),
+            "New in My Feed: My Feed!"
+        );
+        assert_eq!(
+            fill_placeholders("{{unknown}} and {{feed_title", lookup),
+            "{{unknown}} and {{feed_title"
+        );
+    }
+
+    #[test]
+    fn test_build_notifiers() {
+        let notifiers = build_notifiers(
+            // Without images, so no cache is made in the real data directory.
+            &[NotifierConfig::Desktop(DesktopConfig {
+                images: false,
+                ..Default::default()
+            })],
+            &FetchConfig::default(),
+        )
+        .unwrap();
+        let names: Vec<&str> = notifiers.iter().map(|n| n.name()).collect();
+        assert_eq!(names, vec!["desktop"]);
+
+        assert!(
+            build_notifiers(&[], &FetchConfig::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
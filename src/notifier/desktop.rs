@@ -0,0 +1,325 @@
+use async_trait::async_trait;
+use chrono::{Duration, Local};
+use notify_rust::{Notification, NotificationHandle};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use crate::config::{DesktopConfig, DesktopPer, FetchConfig};
+use crate::data::{QueuedAction, queue_action};
+use crate::error::{Error, Result};
+use crate::feed::FeedItem;
+use crate::images::ImageCache;
+use crate::notifier::{NotificationData, Notifier, escape_markup};
+
+const SNOOZE_HOURS: i64 = 1;
+
+// A system notification through notify-rust, opening the newest item when clicked.
+// Per item, each popup can also mark the item read, snooze it or mute its feed.
+// The feed's icon, and an item's own image, are downloaded once and shown with it.
+//
+// Showing a popup returns straight away; waiting for the click happens on a
+// thread of its own, so every feed's popup appears at once and the check can
+// save its results before anyone answers them.
+pub struct DesktopNotifier {
+    per: DesktopPer,
+    max_items: usize,
+    // per item popups shown so far in this check
+    shown: Mutex<usize>,
+    // None for the usual data directory
+    data_path: Option<String>,
+    images: Option<ImageCache>,
+    waiting: Mutex<Vec<JoinHandle<()>>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ItemAction {
+    Open,
+    MarkRead,
+    Snooze,
+    Mute,
+}
+
+impl ItemAction {
+    // "default" is a click on the popup itself.
+    fn from_action(action: &str) -> Option<Self> {
+        match action {
+            "default" | "open" => Some(ItemAction::Open),
+            "read" => Some(ItemAction::MarkRead),
+            "snooze" => Some(ItemAction::Snooze),
+            "mute" => Some(ItemAction::Mute),
+            _ => None,
+        }
+    }
+}
+
+impl DesktopNotifier {
+    // Images are downloaded with the [fetch] settings, like feeds.
+    pub fn new(config: &DesktopConfig, fetch: &FetchConfig) -> Result<Self> {
+        let images = if config.images {
+            Some(ImageCache::new(None, fetch)?)
+        } else {
+            None
+        };
+
+        Ok(DesktopNotifier {
+            per: config.per,
+            max_items: config.max_items,
+            shown: Mutex::new(0),
+            data_path: None,
+            images,
+            waiting: Mutex::new(Vec::new()),
+        })
+    }
+
+    // A popup without its picture is still worth showing, so failed downloads
+    // are left out quietly; plenty of sites have no favicon at all.
+    async fn local_image(&self, url: Option<&str>) -> Option<String> {
+        let path = self.images.as_ref()?.get(url?).await.ok()?;
+        Some(path.to_string_lossy().into_owned())
+    }
+
+    async fn show_feed(&self, notification: &NotificationData) -> Result<()> {
+        let link = notification
+            .latest_item()
+            .and_then(|item| item.link.clone())
+            .unwrap_or_default();
+
+        let mut popup = Notification::new();
+        popup
+            .summary(&notification.create_subject())
+            .body(&notification.create_body())
+            .action("default", "Open");
+        if let Some(icon) = self.local_image(notification.feed_icon.as_deref()).await {
+            popup.icon(&icon);
+        }
+
+        let handle = popup.show().map_err(|e| Error::notify(self.name(), e))?;
+
+        self.wait_in_background(handle, move |action| {
+            if action == "default"
+                && !link.is_empty()
+                && let Err(e) = open::that(&link)
+            {
+                eprintln!("Failed to open link: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    async fn show_item(&self, notification: &NotificationData, item: &FeedItem) -> Result<()> {
+        // Templates see just this item as the feed's latest.
+        let single = NotificationData {
+            items: vec![item.clone()],
+            ..notification.clone()
+        };
+        let summary = match &notification.templates.subject {
+            Some(_) => single.create_subject(),
+            None => item
+                .title
+                .clone()
+                .unwrap_or_else(|| String::from("Untitled")),
+        };
+        let body = match &notification.templates.body {
+            Some(_) => single.create_body(),
+            None => escape_markup(&notification.title),
+        };
+
+        let mut popup = Notification::new();
+        popup.summary(&summary).body(&body);
+        if let Some(icon) = self.local_image(notification.feed_icon.as_deref()).await {
+            popup.icon(&icon);
+        }
+        // notify-rust can't attach images on macOS.
+        #[cfg(not(target_os = "macos"))]
+        if let Some(image) = self.local_image(item.image.as_deref()).await {
+            popup.image_path(&image);
+        }
+        if item.link.is_some() {
+            popup.action("default", "Open").action("open", "Open");
+        }
+        // Without a guid or link there's nothing to remember the item by.
+        if item.identity().is_some() {
+            popup
+                .action("read", "Mark read")
+                .action("snooze", "Snooze 1h");
+        }
+        popup.action("mute", "Mute feed");
+
+        let handle = popup.show().map_err(|e| Error::notify(self.name(), e))?;
+
+        let feed_link = notification.feed_link.clone();
+        let item = item.clone();
+        let data_path = self.data_path.clone();
+        self.wait_in_background(handle, move |action| {
+            let Some(action) = ItemAction::from_action(action) else {
+                return;
+            };
+            if let Err(e) = apply_action(&action, &feed_link, &item, data_path.as_deref()) {
+                eprintln!("Failed to {:?} {}: {}", action, feed_link, e.report());
+            }
+        });
+        Ok(())
+    }
+
+    fn wait_in_background(
+        &self,
+        handle: NotificationHandle,
+        on_action: impl FnOnce(&str) + Send + 'static,
+    ) {
+        let waiting = std::thread::spawn(move || handle.wait_for_action(on_action));
+
+        // The daemon never waits, so forget popups that have been answered.
+        let mut pending = self.waiting.lock().unwrap();
+        pending.retain(|waiting| !waiting.is_finished());
+        pending.push(waiting);
+    }
+}
+
+#[async_trait]
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> &str {
+        "desktop"
+    }
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()> {
+        if self.per == DesktopPer::Feed {
+            return self.show_feed(notification).await;
+        }
+
+        // Past the cap for this check, what's left is summed up per feed.
+        let remaining = {
+            let mut shown = self.shown.lock().unwrap();
+            let remaining = self.max_items.saturating_sub(*shown);
+            let count = remaining.min(notification.items.len());
+            *shown += count;
+            count
+        };
+        let (each, rest) = notification.items.split_at(remaining);
+
+        for item in each {
+            self.show_item(notification, item).await?;
+        }
+        if !rest.is_empty() {
+            self.show_feed(&NotificationData {
+                items: rest.to_vec(),
+                ..notification.clone()
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        *self.shown.lock().unwrap() = 0;
+        Ok(())
+    }
+
+    async fn wait(&self) {
+        let pending = std::mem::take(&mut *self.waiting.lock().unwrap());
+        let joined = tokio::task::spawn_blocking(move || {
+            for waiting in pending {
+                let _ = waiting.join();
+            }
+        });
+        let _ = joined.await;
+    }
+}
+
+// Changes are queued for whichever process saves the data next, which may well
+// be a daemon that loaded it before the click. Opening an item marks it read.
+// Reading one settles it for good, cancelling a snooze from an earlier popup.
+fn apply_action(
+    action: &ItemAction,
+    feed_link: &str,
+    item: &FeedItem,
+    data_path: Option<&str>,
+) -> Result<()> {
+    let feed_link = String::from(feed_link);
+    if *action == ItemAction::Open
+        && let Some(link) = &item.link
+    {
+        open::that(link).map_err(|e| Error::notify("desktop", e))?;
+    }
+
+    let queued = match (action, item.identity()) {
+        (ItemAction::Open | ItemAction::MarkRead, Some(id)) => QueuedAction::MarkRead {
+            feed_link,
+            id: String::from(id),
+        },
+        (ItemAction::Snooze, Some(id)) => QueuedAction::Snooze {
+            feed_link,
+            id: String::from(id),
+            until: (Local::now() + Duration::hours(SNOOZE_HOURS)).to_rfc2822(),
+        },
+        (ItemAction::Mute, _) => QueuedAction::Mute { feed_link },
+        (_, None) => return Ok(()),
+    };
+    queue_action(data_path, &queued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Data;
+    use crate::feed::FeedItem;
+    use crate::notifier::test_notification;
+
+    #[tokio::test]
+    #[ignore] // prevents cargo test from running it by default
+    async fn test_actual_notification() {
+        // Build a fake RSS item
+        let item = FeedItem {
+            title: Some("Test Notification Title".into()),
+            link: Some("https://example.com".into()),
+            ..Default::default()
+        };
+
+        // Build NotificationData
+        let notif = test_notification("Mock Feed", vec![item]);
+
+        // This should trigger a real desktop notification.
+        let notifier =
+            DesktopNotifier::new(&DesktopConfig::default(), &FetchConfig::default()).unwrap();
+        notifier.notify(&notif).await.expect("Notification failed");
+
+        // Keep test alive until the notification is clicked or dismissed
+        notifier.wait().await;
+    }
+
+    #[test]
+    fn test_item_actions() {
+        assert_eq!(ItemAction::from_action("default"), Some(ItemAction::Open));
+        assert_eq!(ItemAction::from_action("read"), Some(ItemAction::MarkRead));
+        assert_eq!(ItemAction::from_action("__closed"), None);
+
+        let path = "./test-desktop-item-actions";
+        let feed_link = "https://example.com/feed";
+        let mut data = Data::load(Some(path)).unwrap();
+        data.insert_link_map(feed_link, "* * * * *");
+        data.save(Some(path)).unwrap();
+
+        let item = FeedItem {
+            id: Some(String::from("guid-1")),
+            ..Default::default()
+        };
+
+        apply_action(&ItemAction::Snooze, feed_link, &item, Some(path)).unwrap();
+        let data = Data::load(Some(path)).unwrap();
+        let feed = data.get_link_map(feed_link).unwrap();
+        assert!(feed.is_snoozed("guid-1"));
+        assert!(!feed.is_snooze_over("guid-1"));
+
+        apply_action(&ItemAction::MarkRead, feed_link, &item, Some(path)).unwrap();
+        let data = Data::load(Some(path)).unwrap();
+        let feed = data.get_link_map(feed_link).unwrap();
+        assert!(!feed.is_snoozed("guid-1"));
+        assert!(feed.is_item_seen("guid-1"));
+        assert!(!feed.is_muted());
+
+        apply_action(&ItemAction::Mute, feed_link, &item, Some(path)).unwrap();
+        let data = Data::load(Some(path)).unwrap();
+        assert!(data.get_link_map(feed_link).unwrap().is_muted());
+
+        std::fs::remove_dir_all(path).ok();
+    }
+}
@@ -0,0 +1,406 @@
+use async_trait::async_trait;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::{EmailConfig, EmailMode, SmtpSecurity};
+use crate::error::{Error, Result};
+use crate::notifier::{NotificationData, Notifier, config_or_env, escape_markup};
+
+const TEXT_WIDTH: usize = 78;
+
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    mode: EmailMode,
+    // feeds waiting for the digest at the end of the check
+    pending: Mutex<Vec<NotificationData>>,
+}
+
+impl EmailNotifier {
+    pub fn new(config: &EmailConfig) -> Result<Self> {
+        let from = parse_mailbox(&config.from)?;
+        let to = config
+            .to
+            .iter()
+            .map(|address| parse_mailbox(address))
+            .collect::<Result<Vec<_>>>()?;
+        if to.is_empty() {
+            return Err(Error::Config {
+                reason: String::from("The email notifier needs at least one `to` address"),
+                source: None,
+            });
+        }
+
+        let invalid_host = |e| Error::config(format!("Invalid SMTP host {}", config.host), e);
+        let mut builder = match config.security {
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                    .map_err(invalid_host)?
+            }
+            SmtpSecurity::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host).map_err(invalid_host)?
+            }
+            SmtpSecurity::Plain => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host).port(25)
+            }
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(credentials) = credentials(config)? {
+            builder = builder.credentials(credentials);
+        }
+        let transport = builder
+            .timeout(Some(Duration::from_secs(config.timeout_secs)))
+            .build();
+
+        Ok(EmailNotifier {
+            transport,
+            from,
+            to,
+            mode: config.mode,
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn send(&self, subject: &str, notifications: &[NotificationData]) -> Result<()> {
+        let mut builder = Message::builder().from(self.from.clone()).subject(subject);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+
+        let message = builder
+            .multipart(MultiPart::alternative_plain_html(
+                render_text(notifications),
+                render_html(notifications),
+            ))
+            .map_err(|e| Error::notify(self.name(), e))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| Error::notify(self.name(), e))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()> {
+        match self.mode {
+            EmailMode::Immediate => {
+                self.send(
+                    &notification.create_subject(),
+                    std::slice::from_ref(notification),
+                )
+                .await
+            }
+            EmailMode::Digest => {
+                self.pending.lock().unwrap().push(notification.clone());
+                Ok(())
+            }
+        }
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let items: usize = pending.iter().map(|n| n.unseen_items_count()).sum();
+        let subject = format!("{} new items from {} feeds", items, pending.len());
+        self.send(&subject, &pending).await
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox> {
+    address
+        .parse()
+        .map_err(|e| Error::config(format!("Invalid email address {}", address), e))
+}
+
+fn credentials(config: &EmailConfig) -> Result<Option<Credentials>> {
+    let username = config_or_env(&config.username, &config.username_env)?;
+    let password = config_or_env(&config.password, &config.password_env)?;
+
+    match (username, password) {
+        (Some(username), Some(password)) => Ok(Some(Credentials::new(username, password))),
+        (None, None) => Ok(None),
+        _ => Err(Error::Config {
+            reason: String::from("The email notifier needs both a username and a password"),
+            source: None,
+        }),
+    }
+}
+
+fn render_text(notifications: &[NotificationData]) -> String {
+    let mut text = String::new();
+
+    for notification in notifications {
+        text.push_str(&format!(
+            "{} ({})\n{} new items\n\n",
+            notification.title,
+            notification.feed_link,
+            notification.unseen_items_count()
+        ));
+
+        for item in &notification.items {
+            text.push_str(&format!(
+                "* {}\n",
+                item.title.as_deref().unwrap_or("Untitled")
+            ));
+            if let Some(link) = &item.link {
+                text.push_str(&format!("  {}\n", link));
+            }
+            if let Some(date) = item.date() {
+                text.push_str(&format!("  {}\n", date.to_rfc2822()));
+            }
+            if let Some(summary) = &item.summary {
+                let summary = html2text::from_read(summary.as_bytes(), TEXT_WIDTH - 2)
+                    .unwrap_or_else(|_| summary.clone());
+                for line in summary.trim().lines() {
+                    text.push_str(&format!("  {}\n", line));
+                }
+            }
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+// Everything from the feed is escaped, summaries included: those are turned
+// into plain text first, keeping their line breaks.
+fn render_html(notifications: &[NotificationData]) -> String {
+    let mut html = String::from("<html><body>\n");
+
+    for notification in notifications {
+        html.push_str(&format!(
+            "<h2><a href=\"{}\">{}</a></h2>\n<ul>\n",
+            escape_markup(&notification.feed_link),
+            escape_markup(&notification.title)
+        ));
+
+        for item in &notification.items {
+            let title = escape_markup(item.title.as_deref().unwrap_or("Untitled"));
+            html.push_str("<li>");
+            match &item.link {
+                Some(link) => html.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_markup(link),
+                    title
+                )),
+                None => html.push_str(&title),
+            }
+            if let Some(date) = item.date() {
+                html.push_str(&format!(" <small>{}</small>", date.to_rfc2822()));
+            }
+            // Feed HTML can carry scripts, trackers and broken markup, so only
+            // its text goes into the mail.
+            if let Some(summary) = &item.summary {
+                let summary = html2text::from_read(summary.as_bytes(), TEXT_WIDTH)
+                    .unwrap_or_else(|_| summary.clone());
+                html.push_str(&format!(
+                    "<div style=\"white-space: pre-line\">{}</div>",
+                    escape_markup(summary.trim())
+                ));
+            }
+            html.push_str("</li>\n");
+        }
+
+        html.push_str("</ul>\n");
+    }
+
+    html.push_str("</body></html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedItem;
+    use crate::notifier::test_notification;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // Just enough of an SMTP server to accept messages, which it keeps.
+    async fn start_smtp_server() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages: Arc<Mutex<Vec<String>>> = Default::default();
+
+        let received = messages.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command = line.to_ascii_uppercase();
+                        let reply: &[u8] = if command.starts_with("EHLO") {
+                            b"250 localhost\r\n"
+                        } else if command.starts_with("DATA") {
+                            write.write_all(b"354 go ahead\r\n").await.unwrap();
+                            let mut message = String::new();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                message.push_str(&line);
+                                message.push('\n');
+                            }
+                            received.lock().unwrap().push(message);
+                            b"250 OK\r\n"
+                        } else if command.starts_with("QUIT") {
+                            write.write_all(b"221 bye\r\n").await.unwrap();
+                            break;
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        write.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        (port, messages)
+    }
+
+    fn config(port: u16, mode: EmailMode) -> EmailConfig {
+        EmailConfig {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            security: SmtpSecurity::Plain,
+            from: String::from("rss-notify <feeds@example.com>"),
+            to: vec![String::from("me@example.com")],
+            username: None,
+            username_env: None,
+            password: None,
+            password_env: None,
+            mode,
+            timeout_secs: 5,
+        }
+    }
+
+    fn notification(title: &str, item_title: &str) -> NotificationData {
+        test_notification(
+            title,
+            vec![FeedItem {
+                title: Some(String::from(item_title)),
+                link: Some(String::from("https://example.com/item")),
+                summary: Some(String::from("<p>Some <b>bold</b> news</p>")),
+                ..Default::default()
+            }],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_email_sends_each_feed_immediately() {
+        let (port, messages) = start_smtp_server().await;
+        let notifier = EmailNotifier::new(&config(port, EmailMode::Immediate)).unwrap();
+
+        notifier
+            .notify(&notification("Feed One", "First post"))
+            .await
+            .unwrap();
+        notifier
+            .notify(&notification("Feed Two", "Second post"))
+            .await
+            .unwrap();
+        notifier.flush().await.unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("Subject: Feed One, 1 unread items!"));
+        assert!(messages[0].contains("text/plain"));
+        assert!(messages[0].contains("text/html"));
+        assert!(messages[1].contains("Second post"));
+    }
+
+    #[tokio::test]
+    async fn test_email_digest_waits_for_flush() {
+        let (port, messages) = start_smtp_server().await;
+        let notifier = EmailNotifier::new(&config(port, EmailMode::Digest)).unwrap();
+
+        notifier
+            .notify(&notification("Feed One", "First post"))
+            .await
+            .unwrap();
+        notifier
+            .notify(&notification("Feed Two", "Second post"))
+            .await
+            .unwrap();
+        assert!(messages.lock().unwrap().is_empty());
+
+        notifier.flush().await.unwrap();
+        notifier.flush().await.unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(
+            messages.len(),
+            1,
+            "One digest, and nothing for an empty flush"
+        );
+        assert!(messages[0].contains("Subject: 2 new items from 2 feeds"));
+        assert!(messages[0].contains("First post"));
+        assert!(messages[0].contains("Second post"));
+    }
+
+    #[test]
+    fn test_render_text_and_html() {
+        let notifications = [notification("Feed <One>", "First post")];
+
+        let text = render_text(&notifications);
+        assert!(text.contains("Feed <One> (https://example.com/feed)"));
+        assert!(text.contains("* First post\n  https://example.com/item\n"));
+        assert!(text.contains("  Some bold news\n"), "{}", text);
+
+        let html = render_html(&notifications);
+        assert!(html.contains("Feed &lt;One&gt;"));
+        assert!(html.contains("<a href=\"https://example.com/item\">First post</a>"));
+        assert!(
+            html.contains("<div style=\"white-space: pre-line\">Some bold news</div>"),
+            "{}",
+            html
+        );
+
+        let mut hostile = notification("Feed", "Post");
+        hostile.items[0].summary = Some(String::from(
+            "<script>alert(1)</script><img src=\"https://tracker.example/p.gif\">Hi &lt;there&gt;",
+        ));
+        let html = render_html(&[hostile]);
+        assert!(!html.contains("<script"), "{}", html);
+        assert!(!html.contains("<img"), "{}", html);
+        assert!(html.contains("Hi &lt;there&gt;"), "{}", html);
+    }
+
+    #[test]
+    fn test_email_credentials_from_env() {
+        let mut config = config(25, EmailMode::Immediate);
+        config.username = Some(String::from("feeds"));
+        config.password_env = Some(String::from("RSS_NOTIFY_TEST_SMTP_PASSWORD_UNSET"));
+        assert!(EmailNotifier::new(&config).is_err(), "Missing variable");
+
+        config.password_env = None;
+        assert!(
+            EmailNotifier::new(&config).is_err(),
+            "Username without password"
+        );
+
+        config.password = Some(String::from("secret"));
+        assert!(EmailNotifier::new(&config).is_ok());
+    }
+}
@@ -0,0 +1,350 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::{ExecConfig, ExecPer};
+use crate::error::{Error, Result};
+use crate::feed::FeedItem;
+use crate::notifier::{NotificationData, Notifier};
+
+const ENV_SUMMARY_LIMIT: usize = 4096;
+
+// Runs a command for every new item, or once per feed, to hand items to
+// downloaders, ticket systems or custom scripts. The details are passed both
+// as RSS_NOTIFY_* environment variables and as JSON on stdin: the item record
+// per item, or the whole notification document per feed. The summary variable
+// is cut to ENV_SUMMARY_LIMIT bytes, since the environment has to fit in the
+// kernel's argument limit; stdin always has all of it.
+pub struct ExecNotifier {
+    program: String,
+    args: Vec<String>,
+    per: ExecPer,
+    timeout: Duration,
+}
+
+impl ExecNotifier {
+    pub fn new(config: &ExecConfig) -> Result<Self> {
+        let Some((program, args)) = config.command.split_first() else {
+            return Err(Error::Config {
+                reason: String::from("The exec notifier needs a command to run"),
+                source: None,
+            });
+        };
+
+        Ok(ExecNotifier {
+            program: program.clone(),
+            args: args.to_vec(),
+            per: config.per,
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    async fn run(
+        &self,
+        notification: &NotificationData,
+        item: Option<&FeedItem>,
+        input: Value,
+    ) -> Result<()> {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .env("RSS_NOTIFY_FEED_TITLE", &notification.title)
+            .env("RSS_NOTIFY_FEED_URL", &notification.feed_link)
+            .env(
+                "RSS_NOTIFY_UNSEEN_COUNT",
+                notification.unseen_items_count().to_string(),
+            )
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(item) = item {
+            let fields = [
+                ("RSS_NOTIFY_ITEM_TITLE", item.title.clone()),
+                ("RSS_NOTIFY_ITEM_LINK", item.link.clone()),
+                ("RSS_NOTIFY_ITEM_GUID", item.id.clone()),
+                (
+                    "RSS_NOTIFY_ITEM_PUBLISHED",
+                    item.date().map(|date| date.to_rfc3339()),
+                ),
+                (
+                    "RSS_NOTIFY_ITEM_SUMMARY",
+                    item.summary
+                        .as_deref()
+                        .map(|summary| truncate(summary, ENV_SUMMARY_LIMIT).to_string()),
+                ),
+            ];
+            for (name, value) in fields {
+                command.env(name, value.unwrap_or_default());
+            }
+        }
+
+        let mut child = command.spawn().map_err(|e| {
+            Error::notify(
+                self.name(),
+                format!("Could not run {}: {}", self.program, e),
+            )
+        })?;
+
+        // Written alongside waiting, so a command that never reads its input
+        // still times out. One that ignores stdin closes the pipe early, which
+        // isn't a failure.
+        let stdin = child.stdin.take();
+        let input = input.to_string();
+        let writer = tokio::spawn(async move {
+            if let Some(mut stdin) = stdin {
+                let _ = stdin.write_all(input.as_bytes()).await;
+            }
+        });
+
+        // Dropping the child on timeout kills it, which also ends the writer.
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                Error::notify(
+                    self.name(),
+                    format!("{} timed out after {:?}", self.program, self.timeout),
+                )
+            })?
+            .map_err(|e| Error::notify(self.name(), e));
+        writer.abort();
+        let output = output?;
+
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut reason = format!("{} exited with {}", self.program, output.status);
+        if !stderr.trim().is_empty() {
+            reason.push_str(&format!(": {}", stderr.trim()));
+        }
+        Err(Error::notify(self.name(), reason))
+    }
+}
+
+#[async_trait]
+impl Notifier for ExecNotifier {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()> {
+        match self.per {
+            ExecPer::Feed => {
+                self.run(
+                    notification,
+                    notification.latest_item(),
+                    notification.to_json(),
+                )
+                .await
+            }
+            ExecPer::Item => {
+                // Every item gets its run; the first failure is reported.
+                let mut result = Ok(());
+                for item in &notification.items {
+                    let run = self
+                        .run(notification, Some(item), notification.item_record(item))
+                        .await;
+                    if result.is_ok() {
+                        result = run;
+                    }
+                }
+                result
+            }
+        }
+    }
+}
+
+// At most max bytes of value, cut on a character boundary.
+fn truncate(value: &str, max: usize) -> &str {
+    if value.len() <= max {
+        return value;
+    }
+    let mut end = max;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::notifier::test_notification;
+
+    fn notification() -> NotificationData {
+        test_notification(
+            "My Feed",
+            vec![
+                FeedItem {
+                    title: Some(String::from("Second")),
+                    link: Some(String::from("https://example.com/2")),
+                    ..Default::default()
+                },
+                FeedItem {
+                    title: Some(String::from("First")),
+                    link: Some(String::from("https://example.com/1")),
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    fn config(script: &str, per: ExecPer) -> ExecConfig {
+        ExecConfig {
+            command: vec![String::from("sh"), String::from("-c"), String::from(script)],
+            per,
+            timeout_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exec_runs_per_item() {
+        let test_path = "./test-exec-notifier-item";
+        std::fs::create_dir_all(test_path).unwrap();
+
+        let script = format!(
+            r#"echo "$RSS_NOTIFY_FEED_TITLE|$RSS_NOTIFY_ITEM_TITLE|$RSS_NOTIFY_ITEM_LINK" >> {0}/env; cat >> {0}/stdin; echo >> {0}/stdin"#,
+            test_path
+        );
+        let notifier = ExecNotifier::new(&config(&script, ExecPer::Item)).unwrap();
+        notifier.notify(&notification()).await.unwrap();
+
+        let env = std::fs::read_to_string(format!("{}/env", test_path)).unwrap();
+        assert_eq!(
+            env,
+            "My Feed|Second|https://example.com/2\nMy Feed|First|https://example.com/1\n"
+        );
+
+        let stdin = std::fs::read_to_string(format!("{}/stdin", test_path)).unwrap();
+        let records: Vec<Value> = stdin
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                notification().item_record(&notification().items[0]),
+                notification().item_record(&notification().items[1]),
+            ]
+        );
+
+        std::fs::remove_dir_all(test_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_exec_runs_once_per_feed() {
+        let test_path = "./test-exec-notifier-feed";
+        std::fs::create_dir_all(test_path).unwrap();
+
+        let script = format!(
+            r#"echo "$RSS_NOTIFY_UNSEEN_COUNT|$RSS_NOTIFY_ITEM_TITLE" >> {0}/env; cat > {0}/stdin"#,
+            test_path
+        );
+        let notifier = ExecNotifier::new(&config(&script, ExecPer::Feed)).unwrap();
+        notifier.notify(&notification()).await.unwrap();
+
+        let env = std::fs::read_to_string(format!("{}/env", test_path)).unwrap();
+        assert_eq!(env, "2|Second\n");
+        let stdin = std::fs::read_to_string(format!("{}/stdin", test_path)).unwrap();
+        let document: Value = serde_json::from_str(&stdin).unwrap();
+        assert_eq!(document, notification().to_json());
+
+        std::fs::remove_dir_all(test_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_exec_reports_exit_status_and_stderr() {
+        let notifier =
+            ExecNotifier::new(&config("echo 'no space left' >&2; exit 3", ExecPer::Feed)).unwrap();
+        let error = notifier.notify(&notification()).await.unwrap_err();
+
+        assert!(matches!(error, Error::Notify { .. }));
+        assert!(
+            error.report().contains("exit status: 3"),
+            "{}",
+            error.report()
+        );
+        assert!(error.report().contains("no space left"));
+    }
+
+    #[tokio::test]
+    async fn test_exec_times_out() {
+        let mut config = config("sleep 10", ExecPer::Feed);
+        config.timeout_secs = 0;
+        let notifier = ExecNotifier::new(&config).unwrap();
+        let error = notifier.notify(&notification()).await.unwrap_err();
+
+        assert!(error.report().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_exec_times_out_without_reading_a_large_payload() {
+        let mut config = config("sleep 10", ExecPer::Feed);
+        config.timeout_secs = 1;
+        let notifier = ExecNotifier::new(&config).unwrap();
+        let items = (0..20)
+            .map(|i| FeedItem {
+                title: Some(format!("Item {}", i)),
+                summary: Some("x".repeat(10 * 1024)),
+                ..Default::default()
+            })
+            .collect();
+
+        let started = std::time::Instant::now();
+        let error = notifier
+            .notify(&test_notification("My Feed", items))
+            .await
+            .unwrap_err();
+
+        assert!(error.report().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_exec_truncates_the_summary_variable() {
+        let test_path = "./test-exec-notifier-summary";
+        std::fs::create_dir_all(test_path).unwrap();
+
+        let script = format!(
+            r#"printf %s "$RSS_NOTIFY_ITEM_SUMMARY" > {0}/env; cat > {0}/stdin"#,
+            test_path
+        );
+        let notifier = ExecNotifier::new(&config(&script, ExecPer::Item)).unwrap();
+        let summary = "é".repeat(ENV_SUMMARY_LIMIT);
+        let item = FeedItem {
+            summary: Some(summary.clone()),
+            ..Default::default()
+        };
+        notifier
+            .notify(&test_notification("My Feed", vec![item]))
+            .await
+            .unwrap();
+
+        let env = std::fs::read_to_string(format!("{}/env", test_path)).unwrap();
+        assert_eq!(env, "é".repeat(ENV_SUMMARY_LIMIT / 2));
+        let stdin = std::fs::read_to_string(format!("{}/stdin", test_path)).unwrap();
+        let record: Value = serde_json::from_str(&stdin).unwrap();
+        assert_eq!(record["summary"], summary.as_str());
+
+        std::fs::remove_dir_all(test_path).ok();
+    }
+
+    #[test]
+    fn test_exec_needs_a_command() {
+        assert!(ExecNotifier::new(&config("true", ExecPer::Item)).is_ok());
+        assert!(
+            ExecNotifier::new(&ExecConfig {
+                command: Vec::new(),
+                per: ExecPer::Item,
+                timeout_secs: 5,
+            })
+            .is_err()
+        );
+    }
+}
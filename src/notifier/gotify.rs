@@ -0,0 +1,144 @@
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde_json::json;
+use std::time::Duration;
+
+use crate::config::GotifyConfig;
+use crate::error::{Error, Result};
+use crate::notifier::{NotificationData, Notifier, config_or_env, send_request};
+
+// Posts to a Gotify server as an application. The click extra opens the newest
+// item from the Android app.
+pub struct GotifyNotifier {
+    client: Client,
+    endpoint: Url,
+    token: String,
+    priority: Option<u8>,
+}
+
+impl GotifyNotifier {
+    pub fn new(config: &GotifyConfig) -> Result<Self> {
+        // Gotify may live under a path, which join would otherwise replace.
+        let server = format!("{}/", config.server.trim_end_matches('/'));
+        let endpoint = Url::parse(&server)
+            .and_then(|server| server.join("message"))
+            .map_err(|e| Error::config(format!("Invalid Gotify server {}", config.server), e))?;
+        let token =
+            config_or_env(&config.token, &config.token_env)?.ok_or_else(|| Error::Config {
+                reason: String::from("The Gotify notifier needs an application token"),
+                source: None,
+            })?;
+        if config.priority.is_some_and(|priority| priority > 10) {
+            return Err(Error::Config {
+                reason: String::from("Gotify priority must be between 0 and 10"),
+                source: None,
+            });
+        }
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .map_err(|e| Error::config("Invalid Gotify settings", e))?;
+
+        Ok(GotifyNotifier {
+            client,
+            endpoint,
+            token,
+            priority: config.priority,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for GotifyNotifier {
+    fn name(&self) -> &str {
+        "gotify"
+    }
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()> {
+        let mut message = json!({
+            "title": notification.create_subject(),
+            "message": notification.create_plain_body(),
+        });
+        if let Some(priority) = self.priority {
+            message["priority"] = json!(priority);
+        }
+        if let Some(link) = notification
+            .latest_item()
+            .and_then(|item| item.link.as_ref())
+        {
+            message["extras"] = json!({
+                "client::notification": { "click": { "url": link } },
+            });
+        }
+
+        let request = self
+            .client
+            .post(self.endpoint.clone())
+            .header("X-Gotify-Key", &self.token)
+            .json(&message);
+
+        send_request(request, &self.endpoint)
+            .await
+            .map_err(|e| Error::notify(self.name(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedItem;
+    use crate::notifier::test_notification;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config(server: String) -> GotifyConfig {
+        GotifyConfig {
+            server,
+            token: Some(String::from("app-token")),
+            token_env: None,
+            priority: Some(8),
+            timeout_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gotify_posts_message() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/gotify/message"))
+            .and(header("X-Gotify-Key", "app-token"))
+            .and(body_json(json!({
+                "title": "My Feed, 1 unread items!",
+                "message": "Latest Item: Hello World\nClick to read more!",
+                "priority": 8,
+                "extras": {
+                    "client::notification": { "click": { "url": "https://example.com/hello" } },
+                },
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = GotifyNotifier::new(&config(format!("{}/gotify", server.uri()))).unwrap();
+        let notification = test_notification(
+            "My Feed",
+            vec![FeedItem {
+                title: Some(String::from("Hello World")),
+                link: Some(String::from("https://example.com/hello")),
+                ..Default::default()
+            }],
+        );
+
+        notifier.notify(&notification).await.unwrap();
+    }
+
+    #[test]
+    fn test_gotify_needs_token() {
+        let mut config = config(String::from("https://gotify.example.com/"));
+        config.token = None;
+
+        assert!(GotifyNotifier::new(&config).is_err());
+    }
+}
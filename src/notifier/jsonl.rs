@@ -0,0 +1,126 @@
+use async_trait::async_trait;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::config::JsonlConfig;
+use crate::error::{Error, Result};
+use crate::notifier::{NotificationData, Notifier};
+
+// One JSON object per new item, for piping into jq or other scripts.
+pub struct JsonlNotifier {
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonlNotifier {
+    pub fn new(config: &JsonlConfig) -> Result<Self> {
+        let output: Box<dyn Write + Send> = match &config.path {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| Error::io(path, e))?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+
+        Ok(JsonlNotifier {
+            output: Mutex::new(output),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for JsonlNotifier {
+    fn name(&self) -> &str {
+        "jsonl"
+    }
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()> {
+        let mut lines = String::new();
+        for item in &notification.items {
+            lines.push_str(&notification.item_record(item).to_string());
+            lines.push('\n');
+        }
+
+        // Written in one go, so records from different feeds never interleave.
+        let mut output = self.output.lock().unwrap();
+        output
+            .write_all(lines.as_bytes())
+            .and_then(|()| output.flush())
+            .map_err(|e| Error::notify(self.name(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedItem;
+    use crate::notifier::test_notification;
+    use chrono::DateTime;
+    use serde_json::{Value, json};
+
+    #[tokio::test]
+    async fn test_jsonl_writes_a_record_per_item() {
+        let test_path = "./test-jsonl-notifier";
+        std::fs::create_dir_all(test_path).unwrap();
+        let path = format!("{}/items.jsonl", test_path);
+
+        let notification = test_notification(
+            "My Feed",
+            vec![
+                FeedItem {
+                    title: Some(String::from("Second")),
+                    link: Some(String::from("https://example.com/2")),
+                    id: Some(String::from("tag:example.com,2024:2")),
+                    published: DateTime::parse_from_rfc3339("2024-11-20T11:00:00Z").ok(),
+                    summary: Some(String::from("<p>Two</p>")),
+                    ..Default::default()
+                },
+                FeedItem {
+                    title: Some(String::from("First")),
+                    ..Default::default()
+                },
+            ],
+        );
+
+        let notifier = JsonlNotifier::new(&JsonlConfig {
+            path: Some(path.clone()),
+        })
+        .unwrap();
+        notifier.notify(&notification).await.unwrap();
+        drop(notifier);
+
+        // a second run appends
+        let notifier = JsonlNotifier::new(&JsonlConfig {
+            path: Some(path.clone()),
+        })
+        .unwrap();
+        notifier.notify(&notification).await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[0],
+            json!({
+                "feed": "My Feed",
+                "feed_url": "https://example.com/feed",
+                "title": "Second",
+                "link": "https://example.com/2",
+                "guid": "tag:example.com,2024:2",
+                "published": "2024-11-20T11:00:00+00:00",
+                "summary": "<p>Two</p>",
+            })
+        );
+        assert_eq!(records[1]["title"], "First");
+        assert_eq!(records[1]["published"], Value::Null);
+
+        std::fs::remove_dir_all(test_path).ok();
+    }
+}
@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde_json::json;
+use std::time::Duration;
+
+use crate::config::NtfyConfig;
+use crate::error::{Error, Result};
+use crate::notifier::{NotificationData, Notifier, config_or_env, send_request};
+
+// Publishes through ntfy's JSON API, so titles aren't limited to ASCII headers.
+// Tapping the notification opens the newest item.
+pub struct NtfyNotifier {
+    client: Client,
+    server: Url,
+    topic: String,
+    token: Option<String>,
+    priority: Option<u8>,
+    tags: Vec<String>,
+}
+
+impl NtfyNotifier {
+    pub fn new(config: &NtfyConfig) -> Result<Self> {
+        let server = Url::parse(&config.server)
+            .map_err(|e| Error::config(format!("Invalid ntfy server {}", config.server), e))?;
+        if config
+            .priority
+            .is_some_and(|priority| !(1..=5).contains(&priority))
+        {
+            return Err(Error::Config {
+                reason: String::from("ntfy priority must be between 1 and 5"),
+                source: None,
+            });
+        }
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .map_err(|e| Error::config("Invalid ntfy settings", e))?;
+
+        Ok(NtfyNotifier {
+            client,
+            server,
+            topic: config.topic.clone(),
+            token: config_or_env(&config.token, &config.token_env)?,
+            priority: config.priority,
+            tags: config.tags.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for NtfyNotifier {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()> {
+        let mut message = json!({
+            "topic": self.topic,
+            "title": notification.create_subject(),
+            "message": notification.create_plain_body(),
+        });
+        if let Some(priority) = self.priority {
+            message["priority"] = json!(priority);
+        }
+        if !self.tags.is_empty() {
+            message["tags"] = json!(self.tags);
+        }
+        if let Some(link) = notification
+            .latest_item()
+            .and_then(|item| item.link.as_ref())
+        {
+            message["click"] = json!(link);
+        }
+
+        let mut request = self.client.post(self.server.clone()).json(&message);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        send_request(request, &self.server)
+            .await
+            .map_err(|e| Error::notify(self.name(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedItem;
+    use crate::notifier::test_notification;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn notification() -> NotificationData {
+        test_notification(
+            "My Feed",
+            vec![FeedItem {
+                title: Some(String::from("Hello World")),
+                link: Some(String::from("https://example.com/hello")),
+                ..Default::default()
+            }],
+        )
+    }
+
+    fn config(server: String) -> NtfyConfig {
+        NtfyConfig {
+            server,
+            topic: String::from("my-feeds"),
+            token: Some(String::from("tk_secret")),
+            token_env: None,
+            priority: Some(4),
+            tags: vec![String::from("newspaper")],
+            timeout_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ntfy_publishes_message() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/"))
+            .and(header("Authorization", "Bearer tk_secret"))
+            .and(body_json(json!({
+                "topic": "my-feeds",
+                "title": "My Feed, 1 unread items!",
+                "message": "Latest Item: Hello World\nClick to read more!",
+                "priority": 4,
+                "tags": ["newspaper"],
+                "click": "https://example.com/hello",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = NtfyNotifier::new(&config(server.uri())).unwrap();
+
+        notifier.notify(&notification()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ntfy_reports_rejection() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        let notifier = NtfyNotifier::new(&config(server.uri())).unwrap();
+        let error = notifier.notify(&notification()).await.unwrap_err();
+
+        assert!(error.report().contains("403"));
+    }
+
+    #[test]
+    fn test_ntfy_rejects_bad_priority() {
+        let mut config = config(String::from("https://ntfy.sh"));
+        config.priority = Some(9);
+
+        assert!(NtfyNotifier::new(&config).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use async_trait::async_trait;
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Url};
+use std::time::Duration;
+
+use crate::config::WebhookConfig;
+use crate::error::{Error, Result};
+use crate::fetch::jittered_backoff;
+use crate::notifier::{NotificationData, Notifier, fill_placeholders, send_request};
+
+// A service asking for a longer pause than this is given up on, rather than
+// holding up the rest of the check.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// POSTs a JSON document about each feed's new items, for chat tools and the like.
+//
+// A `body` template can reshape it: {{items}} and {{payload}} are replaced with
+// JSON, and every other placeholder with a JSON-escaped string, so
+// `"text": "{{feed_title}}: {{latest_title}}"` stays valid JSON. {{subject}} and
+// {{body}} are the notification's own templates, as plain text.
+pub struct WebhookNotifier {
+    client: Client,
+    url: Url,
+    body: Option<String>,
+    max_retries: u32,
+    retry_base_delay: Duration,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &WebhookConfig) -> Result<Self> {
+        let url = Url::parse(&config.url)
+            .map_err(|e| Error::config(format!("Invalid webhook url {}", config.url), e))?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (name, value) in &config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::config(format!("Invalid webhook header {}", name), e))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| Error::config(format!("Invalid value for header {}", name), e))?;
+            headers.insert(name, value);
+        }
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .map_err(|e| Error::config("Invalid webhook settings", e))?;
+
+        Ok(WebhookNotifier {
+            client,
+            url,
+            body: config.body.clone(),
+            max_retries: config.max_retries,
+            retry_base_delay: Duration::from_millis(config.retry_base_delay_ms),
+        })
+    }
+
+    fn render_body(&self, notification: &NotificationData) -> String {
+        let payload = notification.to_json();
+        let Some(template) = &self.body else {
+            return payload.to_string();
+        };
+
+        fill_placeholders(template, |name| match name {
+            "items" => Some(payload["items"].to_string()),
+            "payload" => Some(payload.to_string()),
+            "subject" => Some(json_escape(&notification.create_subject())),
+            "body" => Some(json_escape(&notification.create_plain_body())),
+            _ => notification
+                .placeholder(name)
+                .map(|value| json_escape(&value)),
+        })
+    }
+
+    async fn post(&self, body: &str) -> Result<()> {
+        let request = self.client.post(self.url.clone()).body(body.to_string());
+        send_request(request, &self.url).await
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn notify(&self, notification: &NotificationData) -> Result<()> {
+        let body = self.render_body(notification);
+        let mut attempt = 0;
+
+        loop {
+            let error = match self.post(&body).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            if attempt >= self.max_retries || !error.is_transient() {
+                return Err(Error::notify(self.name(), error));
+            }
+
+            let backoff = jittered_backoff(self.retry_base_delay, attempt);
+            let delay = match error.retry_after() {
+                Some(wait) if wait > MAX_RETRY_AFTER => {
+                    return Err(Error::notify(self.name(), error));
+                }
+                Some(wait) => wait.max(backoff),
+                None => backoff,
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+// The inside of a JSON string literal, without the quotes.
+fn json_escape(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedItem;
+    use crate::notifier::test_notification;
+    use std::collections::BTreeMap;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn notification() -> NotificationData {
+        test_notification(
+            "The \"Daily\" Feed",
+            vec![FeedItem {
+                title: Some(String::from("Hello World")),
+                link: Some(String::from("https://example.com/hello")),
+                ..Default::default()
+            }],
+        )
+    }
+
+    fn config(url: String) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            headers: BTreeMap::new(),
+            body: None,
+            timeout_secs: 5,
+            max_retries: 2,
+            retry_base_delay_ms: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_posts_json_payload_with_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .and(header("Authorization", "Bearer secret"))
+            .and(header("Content-Type", "application/json"))
+            .and(body_json(notification().to_json()))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut config = config(format!("{}/hook", server.uri()));
+        config
+            .headers
+            .insert("Authorization".into(), "Bearer secret".into());
+        let notifier = WebhookNotifier::new(&config).unwrap();
+
+        notifier.notify(&notification()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_renders_body_template() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(serde_json::json!({
+                "text": "The \"Daily\" Feed: Hello World (1 new)",
+                "count": 1,
+                "links": [{
+                    "title": "Hello World",
+                    "link": "https://example.com/hello",
+                    "date": null,
+                    "summary": null,
+                }],
+                "subject": "The \"Daily\" Feed, 1 unread items!",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut config = config(server.uri());
+        config.body = Some(String::from(
+            r#"{"text": "{{feed_title}}: {{latest_title}} ({{unseen_count}} new)", "count": {{unseen_count}}, "links": {{items}}, "subject": "{{subject}}"}"#,
+        ));
+        let notifier = WebhookNotifier::new(&config).unwrap();
+
+        notifier.notify(&notification()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_retries_server_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = WebhookNotifier::new(&config(server.uri())).unwrap();
+
+        notifier.notify(&notification()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_honours_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = WebhookNotifier::new(&config(server.uri())).unwrap();
+        let started = std::time::Instant::now();
+        notifier.notify(&notification()).await.unwrap();
+
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_gives_up_on_long_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = WebhookNotifier::new(&config(server.uri())).unwrap();
+        let error = notifier.notify(&notification()).await.unwrap_err();
+
+        assert!(error.report().contains("429"));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_gives_up_on_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = WebhookNotifier::new(&config(server.uri())).unwrap();
+        let error = notifier.notify(&notification()).await.unwrap_err();
+
+        assert!(matches!(error, Error::Notify { .. }));
+        assert!(error.report().contains("400"));
+    }
+
+    #[test]
+    fn test_webhook_rejects_bad_config() {
+        assert!(WebhookNotifier::new(&config(String::from("not a url"))).is_err());
+
+        let mut bad_header = config(String::from("https://example.com/hook"));
+        bad_header
+            .headers
+            .insert("Bad Header".into(), "value".into());
+        assert!(WebhookNotifier::new(&bad_header).is_err());
+    }
+}